@@ -0,0 +1,284 @@
+//! Books of definitions and tests, and running them.
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{labels::LabelTable, parser::TreeParser, run::Reducer, tree::Net};
+
+#[derive(Debug)]
+pub struct Book {
+    pub defs: BTreeMap<String, Net>,
+    pub tests: Vec<Test>,
+    pub root: Net,
+    pub labels: Arc<LabelTable>,
+    /// How tests and the root are reduced.
+    pub reducer: Reducer,
+}
+
+#[derive(Debug)]
+pub struct Test {
+    /// The name of the definition, or `check#<n>` for the `n`th `check`.
+    pub name: String,
+    /// The net whose normal form is tested.
+    pub net: Net,
+    pub expect: Expect,
+}
+
+#[derive(Debug)]
+pub enum Expect {
+    /// Report whether the normal form is coherent.
+    Report,
+    /// The normal form must be alpha-equivalent to this net's.
+    NormalForm(Net),
+    /// The normal form must be coherent, or incoherent.
+    Coherence(bool),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// The source is not a valid book or net.
+    Parse(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Parse(e) => write!(f, "parse error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResult {
+    pub name: String,
+    /// The normal form of the test's net.
+    pub normal: Net,
+    pub outcome: Outcome,
+    /// Interactions taken to reach the normal form.
+    pub interactions: usize,
+    /// The most nodes the net had while reducing.
+    pub max_size: usize,
+    /// Time taken to normalize the net and check it.
+    pub elapsed: Duration,
+}
+
+/// The normal form of the root of a book.
+#[derive(Debug)]
+pub struct RootResult {
+    pub normal: Net,
+    /// `None` if coherence is unknown, as it may be when the root has no
+    /// normal form.
+    pub coherent: Option<bool>,
+    pub interactions: usize,
+    pub max_size: usize,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Coherence {
+        /// `None` if coherence is unknown, as it may be for nets without a
+        /// normal form.
+        coherent: Option<bool>,
+        /// What a `check` expected; `None` for tests that only report.
+        expected: Option<bool>,
+        /// Whether the net was coherent before normalizing, which should not
+        /// differ from `coherent`.
+        before: Option<bool>,
+        /// Whether its root tree alone was coherent before normalizing.
+        root_only: bool,
+    },
+    NormalForm {
+        /// The normal form of the expected net.
+        expected: Net,
+        matches: bool,
+    },
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        match &self.outcome {
+            Outcome::Coherence {
+                coherent, expected, ..
+            } => Some(expected.unwrap_or(true)) == *coherent,
+            Outcome::NormalForm { matches, .. } => *matches,
+        }
+    }
+}
+
+impl Book {
+    pub fn load(path: impl AsRef<Path>) -> Result<Book, Error> {
+        Book::parse(&std::fs::read_to_string(path)?)
+    }
+    pub fn parse(code: &str) -> Result<Book, Error> {
+        TreeParser::new(code).parse_book().map_err(Error::Parse)
+    }
+    /// Parses a net that may refer to the definitions of the book, and
+    /// returns its normal form.
+    pub fn eval(&self, expr: &str) -> Result<Net, Error> {
+        let mut parser = TreeParser::with_defs(expr, self.defs.clone());
+        let mut net = parser.parse_net().map_err(Error::Parse)?;
+        net.labels = self.labels.clone();
+        net.reduce(self.reducer);
+        net.resolve_vars();
+        Ok(net)
+    }
+    pub fn run_tests(&self) -> Vec<TestResult> {
+        self.tests.iter().map(|test| self.run_test(test)).collect()
+    }
+    /// Runs the tests on `jobs` threads. The results are in the same order as
+    /// the tests.
+    pub fn run_tests_parallel(&self, jobs: usize) -> Vec<TestResult> {
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs.max(1))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(test) = self.tests.get(i) else {
+                                break results;
+                            };
+                            results.push((i, self.run_test(test)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|x| x.join().unwrap())
+                .collect()
+        });
+        results.sort_by_key(|x| x.0);
+        results.into_iter().map(|x| x.1).collect()
+    }
+    pub fn run_root(&self) -> RootResult {
+        let start = Instant::now();
+        let mut normal = self.root.clone();
+        let stats = normal.reduce(self.reducer);
+        let coherent = normal.is_coherent();
+        RootResult {
+            normal,
+            coherent,
+            interactions: stats.interactions,
+            max_size: stats.max_size,
+            elapsed: start.elapsed(),
+        }
+    }
+    fn run_test(&self, test: &Test) -> TestResult {
+        let mut net = test.net.clone();
+        let before = net.clone().is_coherent();
+        let root_only = net.clone().is_root_coherent();
+        let start = Instant::now();
+        let stats = net.reduce(self.reducer);
+        let outcome = match &test.expect {
+            Expect::Report | Expect::Coherence(_) => Outcome::Coherence {
+                coherent: net.is_coherent(),
+                expected: match test.expect {
+                    Expect::Coherence(expected) => Some(expected),
+                    _ => None,
+                },
+                before,
+                root_only,
+            },
+            Expect::NormalForm(expected) => {
+                let mut expected = expected.clone();
+                expected.reduce(self.reducer);
+                Outcome::NormalForm {
+                    matches: net.alpha_eq(&expected),
+                    expected,
+                }
+            }
+        };
+        net.resolve_vars();
+        TestResult {
+            name: test.name.clone(),
+            normal: net,
+            outcome,
+            interactions: stats.interactions,
+            max_size: stats.max_size,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+#[test]
+fn session() {
+    let book = Book::parse(include_str!("../sample.itt")).unwrap();
+    let results = book.run_tests();
+    assert_eq!(results.len(), book.tests.len());
+    assert!(results
+        .iter()
+        .all(|x| x.passed() != x.name.starts_with("badtest_")));
+    let net = book.eval("term (Bool.not Bool.false)").unwrap();
+    assert!(net.alpha_eq(&book.defs["Bool.true"]));
+    assert!(matches!(book.eval("(a b)"), Err(Error::Parse(_))));
+    assert!(matches!(Book::parse("term <a b>"), Err(Error::Parse(_))));
+    assert!(matches!(Book::load("missing.itt"), Err(Error::Io(_))));
+}
+
+#[test]
+fn parallel_tests() {
+    let book = Book::parse(include_str!("../sample.itt")).unwrap();
+    let names = |results: Vec<TestResult>| -> Vec<_> {
+        results
+            .into_iter()
+            .map(|x| (x.name.clone(), x.passed(), x.normal.display()))
+            .collect()
+    };
+    assert_eq!(names(book.run_tests_parallel(4)), names(book.run_tests()));
+}
+
+#[test]
+fn normal_form_tests() {
+    let book = Book::parse(
+        "def Bool.true = ((a a) (b b))
+         def Bool.not = (((a b) (c d)) ((d b) (c a)))
+         def Bool.false = ((a b) (a b))
+         def test same = term (Bool.not Bool.true) ~> Bool.false
+         def test differs = term (Bool.not Bool.true) ~> Bool.true
+         (x x)",
+    )
+    .unwrap();
+    let results = book.run_tests();
+    assert!(results[0].passed());
+    assert!(!results[1].passed());
+    // What the CLI shows as the difference.
+    let mut normal = results[1].normal.clone();
+    let Outcome::NormalForm { expected, matches } = &results[1].outcome else {
+        panic!("{:?}", results[1].outcome)
+    };
+    let mut expected = expected.clone();
+    assert!(!matches);
+    normal.canonicalize();
+    expected.canonicalize();
+    assert_ne!(normal.display(), expected.display());
+    assert!(normal.alpha_eq(&book.defs["Bool.false"]));
+}
+
+#[test]
+fn lazy_root() {
+    let mut book = Book::parse("term λz (z (λx (x x) λy (y y)))").unwrap();
+    book.reducer = Reducer::Lazy;
+    let root = book.run_root();
+    assert!(!root.normal.redexes.is_empty());
+    assert_eq!(root.coherent, None);
+}
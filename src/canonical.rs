@@ -0,0 +1,320 @@
+//! Canonical forms of nets, so that nets can be compared up to the naming of
+//! their wires and the order of their redexes.
+use std::{
+    collections::BTreeMap,
+    hash::{Hash, Hasher},
+};
+
+use slotmap::{DefaultKey as SlotKey, SlotMap};
+
+use crate::tree::{Net, Tree};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Token {
+    Node(u64),
+    Var(usize),
+    Redex,
+    Hole(String),
+}
+
+// Numbers variables in order of first occurrence. Variables seen for the first
+// time are only recorded in `fresh`, so that candidates can be compared
+// without committing to their numbering.
+struct Numbering<'a> {
+    known: &'a BTreeMap<SlotKey, usize>,
+    fresh: BTreeMap<SlotKey, usize>,
+}
+
+impl Numbering<'_> {
+    fn tokens(&mut self, tree: &Tree, out: &mut Vec<Token>) {
+        tree.recurse_ref(&mut |tree| match tree {
+            Tree::Binary { label, .. } => out.push(Token::Node(label.0)),
+            Tree::Var { id } => {
+                let n = match self.known.get(id) {
+                    Some(n) => *n,
+                    None => {
+                        let next = self.known.len() + self.fresh.len();
+                        *self.fresh.entry(*id).or_insert(next)
+                    }
+                };
+                out.push(Token::Var(n))
+            }
+        });
+    }
+}
+
+// Where a variable occurs: at which leaf of the root or a hole, or of a side
+// of a redex, with the colors of that side and of the other one.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Occurrence {
+    Root(usize),
+    Hole(String, usize),
+    Redex([usize; 2], usize),
+}
+
+// The variables of a tree, in order.
+fn leaves(tree: &Tree) -> Vec<SlotKey> {
+    let mut leaves = vec![];
+    tree.recurse_ref(&mut |tree| {
+        if let Tree::Var { id } = tree {
+            leaves.push(*id);
+        }
+    });
+    leaves
+}
+
+// Numbers the distinct values in order, and maps each to its number.
+fn ranks<T: Ord>(values: impl Iterator<Item = T>) -> BTreeMap<T, usize> {
+    let mut ranks: BTreeMap<T, usize> = values.map(|x| (x, 0)).collect();
+    for (n, rank) in ranks.values_mut().enumerate() {
+        *rank = n;
+    }
+    ranks
+}
+
+impl Net {
+    // Colors the variables by what they connect, refining the colors until
+    // they tell no more variables apart. Unlike the numbering, colors do not
+    // depend on the order of the redexes, so they break ties between redexes
+    // that look the same from what has been written so far.
+    fn colors(&self) -> BTreeMap<SlotKey, usize> {
+        let mut colors = BTreeMap::new();
+        let mut distinct = 0;
+        loop {
+            let shade = |tree: &Tree| {
+                let mut out = vec![];
+                tree.recurse_ref(&mut |tree| match tree {
+                    Tree::Binary { label, .. } => out.push(Token::Node(label.0)),
+                    Tree::Var { id } => out.push(Token::Var(colors.get(id).copied().unwrap_or(0))),
+                });
+                out
+            };
+            let sides: Vec<_> = self
+                .redexes
+                .iter()
+                .map(|(a, b)| [shade(a), shade(b)])
+                .collect();
+            let side_ranks = ranks(sides.iter().flatten());
+            let mut occurrences = BTreeMap::<SlotKey, Vec<Occurrence>>::new();
+            let mut add = |tree: &Tree, at: &dyn Fn(usize) -> Occurrence| {
+                for (n, id) in leaves(tree).into_iter().enumerate() {
+                    occurrences.entry(id).or_default().push(at(n));
+                }
+            };
+            add(&self.root, &Occurrence::Root);
+            for (name, tree) in &self.holes {
+                add(tree, &|n| Occurrence::Hole(name.clone(), n));
+            }
+            for ((a, b), [x, y]) in self.redexes.iter().zip(&sides) {
+                let (x, y) = (side_ranks[x], side_ranks[y]);
+                add(a, &|n| Occurrence::Redex([x, y], n));
+                add(b, &|n| Occurrence::Redex([y, x], n));
+            }
+            let signatures: BTreeMap<_, _> = occurrences
+                .into_iter()
+                .map(|(id, mut at)| {
+                    at.sort();
+                    (id, (colors.get(&id).copied().unwrap_or(0), at))
+                })
+                .collect();
+            let refined = ranks(signatures.values());
+            if refined.len() == distinct {
+                return colors;
+            }
+            distinct = refined.len();
+            colors = signatures.iter().map(|(id, x)| (*id, refined[x])).collect();
+        }
+    }
+    // Orders and orients the redexes, returning the numbering of the
+    // variables and the token stream of the net.
+    fn canonical_order(&mut self) -> (BTreeMap<SlotKey, usize>, Vec<Token>) {
+        self.resolve_vars();
+        let colors = self.colors();
+        let mut known = BTreeMap::new();
+        let mut tokens = vec![];
+        let mut numbering = Numbering {
+            known: &known,
+            fresh: BTreeMap::new(),
+        };
+        numbering.tokens(&self.root, &mut tokens);
+        for (name, tree) in &self.holes {
+            tokens.push(Token::Hole(name.clone()));
+            numbering.tokens(tree, &mut tokens);
+        }
+        known.append(&mut numbering.fresh);
+
+        // Pick the redex and orientation with the least token stream, given
+        // the variables numbered so far, and then with the least colors.
+        // Redexes connected to what has already been written sort before
+        // unrelated ones.
+        let mut remaining = Vec::from(core::mem::take(&mut self.redexes));
+        while !remaining.is_empty() {
+            let mut best: Option<(Vec<Token>, Vec<usize>, usize, bool)> = None;
+            for (i, (a, b)) in remaining.iter().enumerate() {
+                for swap in [false, true] {
+                    let (a, b) = if swap { (b, a) } else { (a, b) };
+                    let mut numbering = Numbering {
+                        known: &known,
+                        fresh: BTreeMap::new(),
+                    };
+                    let mut candidate = vec![Token::Redex];
+                    numbering.tokens(a, &mut candidate);
+                    numbering.tokens(b, &mut candidate);
+                    let shades = leaves(a).into_iter().chain(leaves(b));
+                    let shades: Vec<_> = shades.map(|x| colors[&x]).collect();
+                    if best
+                        .as_ref()
+                        .is_none_or(|best| (&candidate, &shades) < (&best.0, &best.1))
+                    {
+                        best = Some((candidate, shades, i, swap));
+                    }
+                }
+            }
+            let (mut candidate, _, i, swap) = best.unwrap();
+            let (a, b) = remaining.remove(i);
+            let (a, b) = if swap { (b, a) } else { (a, b) };
+            let mut numbering = Numbering {
+                known: &known,
+                fresh: BTreeMap::new(),
+            };
+            numbering.tokens(&a, &mut vec![]);
+            numbering.tokens(&b, &mut vec![]);
+            known.append(&mut numbering.fresh);
+            tokens.append(&mut candidate);
+            self.redexes.push_back((a, b));
+        }
+        (known, tokens)
+    }
+    /// Renumbers the variables of the net in order of first occurrence and
+    /// puts its redexes in a deterministic order, so that alpha-equivalent
+    /// nets become structurally equal. Redexes whose trees have the same shape
+    /// are ordered by how they connect to the rest of the net, refined until
+    /// stable, which tells apart all but some very symmetric nets.
+    pub fn canonicalize(&mut self) {
+        let (numbering, _) = self.canonical_order();
+        let mut order: Vec<_> = numbering.into_iter().collect();
+        order.sort_by_key(|x| x.1);
+        let mut vars = SlotMap::new();
+        let mut names = BTreeMap::new();
+        let mut remap = BTreeMap::new();
+        for (old, _) in order {
+            let new = vars.insert(None);
+            if let Some(name) = self.names.remove(&old) {
+                names.insert(new, name);
+            }
+            remap.insert(old, new);
+        }
+        self.recurse_mut(&mut |tree| tree.map_var_id(|id| remap.get(&id).cloned()));
+        self.vars = vars;
+        self.names = names;
+    }
+    fn canonical_tokens(&self) -> Vec<Token> {
+        self.clone().canonical_order().1
+    }
+    /// Whether the two nets are equal up to renaming of wires and reordering
+    /// of redexes.
+    pub fn alpha_eq(&self, other: &Net) -> bool {
+        self.canonical_tokens() == other.canonical_tokens()
+    }
+    /// A hash of the canonical form of the net, which is the same for
+    /// alpha-equivalent nets and does not depend on the Rust version.
+    pub fn structural_hash(&self) -> u64 {
+        // 64-bit FNV-1a.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let mut write = |x: u64| {
+            for byte in x.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+        for token in self.canonical_tokens() {
+            match token {
+                Token::Node(label) => {
+                    write(0);
+                    write(label)
+                }
+                Token::Var(n) => {
+                    write(1);
+                    write(n as u64)
+                }
+                Token::Redex => write(2),
+                Token::Hole(name) => {
+                    write(3);
+                    write(name.len() as u64);
+                    for byte in name.bytes() {
+                        write(byte as u64)
+                    }
+                }
+            }
+        }
+        hash
+    }
+}
+
+impl PartialEq for Net {
+    fn eq(&self, other: &Self) -> bool {
+        self.alpha_eq(other)
+    }
+}
+
+impl Eq for Net {}
+
+impl Hash for Net {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.structural_hash())
+    }
+}
+
+#[test]
+fn alpha_equivalence() {
+    use crate::parser::TreeParser;
+    let book = TreeParser::new(
+        "def A = (a (b c)) & b = (d d) & c = (e (a e))
+         def B = (x (y z)) & (w (x w)) = z & y = (v v)
+         def C = (x (y z)) & (w (x w)) = z & y = (v u) & v = u
+         def D = (x (y z)) & (w (x w)) = y & z = (v v)
+         (a a)",
+    )
+    .parse_book()
+    .unwrap();
+    let [a, b, c, d] = ["A", "B", "C", "D"].map(|x| &book.defs[x]);
+    assert!(a.alpha_eq(b));
+    assert_eq!(a.structural_hash(), b.structural_hash());
+    assert!(!a.alpha_eq(c));
+    assert!(!a.alpha_eq(d));
+    let mut canonical = b.clone();
+    canonical.canonicalize();
+    assert_eq!(canonical.display(), "(x (y z)) & (w (x w)) = z & (v v) = y");
+    let set: std::collections::HashSet<Net> = [a, b, c, d].into_iter().cloned().collect();
+    assert_eq!(set.len(), 3);
+
+    let holes = |src| TreeParser::new(src).parse_net().unwrap();
+    let a = holes("(x y) & ?a = x & ?b = y");
+    let b = holes("(x y) & ?b = x & ?a = y");
+    assert!(!a.alpha_eq(&b));
+    assert_ne!(a.structural_hash(), b.structural_hash());
+    assert!(a.alpha_eq(&holes("(u v) & ?b = v & ?a = u")));
+    assert!(!holes("(x x) & ?a = (y y)").alpha_eq(&holes("(x x) & ?b = (y y)")));
+}
+
+#[test]
+fn redex_order() {
+    let net = |src: String| crate::parser::TreeParser::new(&src).parse_net().unwrap();
+    let redexes = [
+        "(a b) = (c d)",
+        "(e f) = (g h)",
+        "(a e) = (b f)",
+        "(c g) = (h d)",
+    ];
+    let first = net(format!("(r r) & {}", redexes.join(" & ")));
+    for i in 0..redexes.len() {
+        for j in 0..redexes.len() {
+            let mut order = redexes.to_vec();
+            order.swap(0, i);
+            order.swap(1, j);
+            let other = net(format!("(r r) & {}", order.join(" & ")));
+            assert!(first.alpha_eq(&other), "{}", other.display());
+            assert_eq!(first.structural_hash(), other.structural_hash());
+        }
+    }
+}
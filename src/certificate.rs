@@ -0,0 +1,281 @@
+//! Checks the certificates written by `Net::certificate`. This only uses the
+//! shape of the normal form, not the path stacks of `coherence.rs`, so that a
+//! verified certificate does not depend on trusting them.
+//!
+//! A certificate file has a section for each net, starting with a line
+//! `net <name>`, where `root` stands for the root of the book.
+use std::collections::BTreeMap;
+
+use slotmap::DefaultKey;
+
+use crate::{
+    book::Book,
+    labels::LabelTable,
+    tree::{Net, NodeLabel, Tree},
+};
+
+pub(crate) type Path = BTreeMap<NodeLabel, (Vec<bool>, Vec<bool>)>;
+
+struct Entry {
+    leaves: (usize, usize),
+    path: Path,
+}
+
+fn parse_bits(word: &str) -> Result<Vec<bool>, String> {
+    if word == "-" {
+        return Ok(vec![]);
+    }
+    word.chars()
+        .map(|c| match c {
+            'l' => Ok(true),
+            'r' => Ok(false),
+            _ => Err(format!("`{word}` is not a stack")),
+        })
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Result<Entry, String> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    let number = |word: &str| {
+        word.parse()
+            .map_err(|_| format!("`{word}` is not a number"))
+    };
+    if words.len() < 2 || words.len() % 3 != 2 {
+        return Err(format!("malformed line `{line}`"));
+    }
+    let mut path = Path::new();
+    for item in words[2..].chunks(3) {
+        let label = NodeLabel(number(item[0])? as u64);
+        path.insert(label, (parse_bits(item[1])?, parse_bits(item[2])?));
+    }
+    Ok(Entry {
+        leaves: (number(words[0])?, number(words[1])?),
+        path,
+    })
+}
+
+// The variable at each leaf of the tree, and the ports taken to reach it.
+fn leaves(tree: &Tree, address: &mut Vec<(NodeLabel, bool)>, out: &mut Vec<(DefaultKey, Path)>) {
+    match tree {
+        Tree::Binary { label, p1, p2 } => {
+            for (first, p) in [(true, p1), (false, p2)] {
+                address.push((*label, first));
+                leaves(p, address, out);
+                address.pop();
+            }
+        }
+        Tree::Var { id } => {
+            let mut path = Path::new();
+            for (label, first) in address.iter() {
+                path.entry(*label).or_default().0.push(*first);
+            }
+            out.push((*id, path));
+        }
+    }
+}
+
+// The path that goes down to one leaf and back up from the other.
+fn between(down: &Path, up: &Path) -> Path {
+    let mut path = down.clone();
+    for (label, (ports, _)) in up {
+        path.entry(*label).or_default().1 = ports.iter().rev().copied().collect();
+    }
+    path
+}
+
+// Pads every path, by going further down on each label and coming back up
+// the same way, until all inputs on a label have the same length, in every
+// way. Then two paths conflict if some of their paddings have the same
+// inputs but different outputs.
+//
+// The paddings of two paths on a label have the same inputs only if one
+// input extends the other, and then the padding of the shorter one starts
+// with the rest of the longer one, so whether their outputs agree does not
+// depend on the padding. This decides it for one label, and whether no
+// paddings have the same inputs.
+fn agree(x: &(Vec<bool>, Vec<bool>), y: &(Vec<bool>, Vec<bool>), padded: bool) -> Option<bool> {
+    if !padded {
+        return (x.0 == y.0).then_some(x.1 == y.1);
+    }
+    let (short, long) = if x.0.len() <= y.0.len() {
+        (x, y)
+    } else {
+        (y, x)
+    };
+    let rest = long.0.strip_prefix(short.0.as_slice())?;
+    let output = rest.iter().rev().chain(&short.1);
+    Some(long.1.len() == rest.len() + short.1.len() && long.1.iter().eq(output))
+}
+
+// The first two paths that conflict, checked one pair at a time.
+fn find_conflict(paths: &[Path], labels: &LabelTable) -> Option<(usize, usize)> {
+    let empty = (vec![], vec![]);
+    let conflict = |x: &Path, y: &Path| {
+        let mut differ = false;
+        for label in x.keys().chain(y.keys()) {
+            if labels.is_transparent(*label) {
+                continue;
+            }
+            let (a, b) = (x.get(label), y.get(label));
+            // Commuting labels are not padded, since their nodes do not
+            // annihilate with the ones a padding would go through.
+            let padded = labels.annihilates(*label);
+            match agree(a.unwrap_or(&empty), b.unwrap_or(&empty), padded) {
+                Some(same) => differ |= !same,
+                None => return false,
+            }
+        }
+        differ
+    };
+    (0..paths.len()).find_map(|n| {
+        (0..n)
+            .find(|&m| conflict(&paths[m], &paths[n]))
+            .map(|m| (m, n))
+    })
+}
+
+/// Checks that `certificate` lists every variable of the normal form of the
+/// net with the path between its two occurrences, and that no two of these
+/// paths conflict.
+pub fn verify(net: &Net, certificate: &str) -> Result<(), String> {
+    let mut net = net.clone();
+    net.resolve_vars();
+    if !net.redexes.is_empty() {
+        return Err("the net is not in normal form".to_string());
+    }
+    let mut found = vec![];
+    leaves(&net.root, &mut vec![], &mut found);
+    let entries = certificate
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_entry)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut covered = BTreeMap::new();
+    for entry in &entries {
+        let (i, j) = entry.leaves;
+        let (Some((a, down)), Some((b, up))) = (found.get(i), found.get(j)) else {
+            return Err(format!("there are no leaves {i} and {j}"));
+        };
+        if i >= j || a != b {
+            return Err(format!("leaves {i} and {j} are not the same variable"));
+        }
+        if covered.insert(*a, (i, j)).is_some() {
+            return Err(format!("leaves {i} and {j} are listed twice"));
+        }
+        if between(down, up) != entry.path {
+            return Err(format!("wrong path between leaves {i} and {j}"));
+        }
+    }
+    if covered.len() * 2 != found.len() {
+        return Err("some variables are missing".to_string());
+    }
+    let paths: Vec<_> = entries.iter().map(|x| x.path.clone()).collect();
+    if let Some((a, b)) = find_conflict(&paths, &net.labels) {
+        let ((i, j), (k, l)) = (entries[a].leaves, entries[b].leaves);
+        return Err(format!("paths {i}-{j} and {k}-{l} conflict"));
+    }
+    Ok(())
+}
+
+/// Verifies each section of a certificate file against the normal form of
+/// the net it names in the book, a test or a definition.
+pub fn verify_book(book: &Book, file: &str) -> Vec<(String, Result<(), String>)> {
+    let mut sections: Vec<(&str, String)> = vec![];
+    for line in file.lines() {
+        match (line.strip_prefix("net "), sections.last_mut()) {
+            (Some(name), _) => sections.push((name, String::new())),
+            (None, Some((_, certificate))) => *certificate += &format!("{line}\n"),
+            (None, None) => (),
+        }
+    }
+    let mut results = vec![];
+    for (name, certificate) in sections {
+        let net = match name {
+            "root" => Some(&book.root),
+            _ => match book.tests.iter().find(|x| x.name == name) {
+                Some(test) => Some(&test.net),
+                None => book.defs.get(name),
+            },
+        };
+        let result = match net {
+            Some(net) => {
+                let mut net = net.clone();
+                net.normal(|_| ());
+                verify(&net, &certificate)
+            }
+            None => Err(format!("no definition `{name}`")),
+        };
+        results.push((name.to_string(), result));
+    }
+    results
+}
+
+#[test]
+fn certificates() {
+    let book = crate::parser::TreeParser::new(include_str!("../sample.itt"))
+        .parse_book()
+        .unwrap();
+    let mut certified = 0;
+    for test in &book.tests {
+        let mut net = test.net.clone();
+        net.normal(|_| ());
+        if let Some(certificate) = net.certificate().unwrap() {
+            assert_eq!(verify(&net, &certificate), Ok(()), "{}", test.name);
+            certified += 1;
+        }
+    }
+    assert!(certified > 0);
+
+    let mut check = book
+        .tests
+        .iter()
+        .find(|x| x.name == "check#1")
+        .unwrap()
+        .net
+        .clone();
+    check.normal(|_| ());
+    let file = format!("net check#1\n{}", check.certificate().unwrap().unwrap());
+    assert_eq!(verify_book(&book, &file), [("check#1".to_string(), Ok(()))]);
+
+    let mut net = book.defs["badtest_1"].clone();
+    assert!(net.certificate().unwrap_err().contains("normal form"));
+    assert!(net.incoherence().is_err());
+    net.normal(|_| ());
+    assert_eq!(net.certificate(), Ok(None));
+    assert!(net.incoherence().unwrap().is_some());
+    let mut opaque = net.clone();
+    let mut table = LabelTable::default();
+    let mut eql = table.get(NodeLabel::EQL).into_owned();
+    eql.transparent = false;
+    table.set(NodeLabel::EQL, eql);
+    opaque.labels = std::sync::Arc::new(table);
+    let certificate = opaque.certificate().unwrap().unwrap();
+    assert!(verify(&net, &certificate).unwrap_err().contains("conflict"));
+    assert!(verify(&net, "").unwrap_err().contains("missing"));
+}
+
+#[test]
+fn padding_matches_coherence() {
+    let book = crate::parser::TreeParser::new(include_str!("../sample.itt"))
+        .parse_book()
+        .unwrap();
+    let tests = book.tests.iter().map(|x| x.net.clone());
+    for mut net in tests.chain(crate::enumerate::Enumerator::new(3)) {
+        if !net.normal_within(crate::equiv::MAX_INTERACTIONS) {
+            continue;
+        }
+        net.resolve_vars();
+        let mut found = vec![];
+        leaves(&net.root, &mut vec![], &mut found);
+        let mut paths = vec![];
+        for (j, (b, up)) in found.iter().enumerate() {
+            for (a, down) in &found[..j] {
+                if a == b {
+                    paths.push(between(down, up));
+                }
+            }
+        }
+        let padded = find_conflict(&paths, &net.labels).is_none();
+        assert_eq!(Some(padded), net.is_coherent(), "{}", net.display());
+    }
+}
@@ -1,8 +1,11 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use slotmap::DefaultKey;
 
-use crate::tree::{NodeLabel, Tree};
+use crate::{
+    labels::LabelTable,
+    tree::{Net, NodeLabel, Tree},
+};
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct PathItem {
@@ -82,9 +85,33 @@ impl NormalPathStack {
     fn neg_len(&self) -> usize {
         self.0.len()
     }
+    // The same path, walked the other way.
+    fn reversed(&self) -> Self {
+        NormalPathStack(
+            self.1.iter().rev().copied().collect(),
+            self.0.iter().rev().copied().collect(),
+        )
+    }
 }
 
 impl NormalPathStackSet {
+    // Whether a path that leaves and re-enters a tree through the same place
+    // is walked the way a traversal of the tree would walk it: towards the
+    // earlier leaf first. This is read off the first label whose input and
+    // output addresses differ.
+    fn leftward(&self) -> bool {
+        for stack in self.0.values() {
+            let output = stack.1.iter().rev();
+            if let Some((input, _)) = stack.0.iter().zip(output).find(|(x, y)| x != y) {
+                return *input;
+            }
+        }
+        true
+    }
+    // The same path, walked the other way.
+    fn reversed(&self) -> Self {
+        NormalPathStackSet(self.0.iter().map(|(k, v)| (*k, v.reversed())).collect())
+    }
     fn neg_len(&self) -> BTreeMap<NodeLabel, usize> {
         self.0
             .clone()
@@ -97,7 +124,7 @@ impl NormalPathStackSet {
         let it = max_len
             .keys()
             .chain(self.0.keys())
-            .map(|x| *x)
+            .copied()
             .collect::<Vec<_>>();
         for i in it {
             let max_len = max_len.get(&i).cloned().unwrap_or_default();
@@ -163,14 +190,21 @@ impl std::fmt::Debug for NormalPathStackSet {
 }
 
 impl Tree {
-    pub fn is_coherent(&self) -> bool {
+    // The paths from the root to each variable and back, with the leaves of
+    // the two occurrences of the variable, in traversal order.
+    fn leaf_paths(&self) -> Vec<((usize, usize), NormalPathStackSet)> {
         #[derive(Default)]
         struct State {
-            vars: BTreeMap<DefaultKey, PathStackSet>,
+            vars: BTreeMap<DefaultKey, (usize, PathStackSet)>,
+            leaves: usize,
         }
 
         impl State {
-            fn traverse(&mut self, tree: &Tree, execution: &PathStackSet) -> Vec<PathStackSet> {
+            fn traverse(
+                &mut self,
+                tree: &Tree,
+                execution: &PathStackSet,
+            ) -> Vec<((usize, usize), PathStackSet)> {
                 match tree {
                     Tree::Binary { label, p1, p2 } => {
                         let label = *label;
@@ -187,14 +221,14 @@ impl Tree {
                         });
                         let mut ls = self.traverse(p1, &ls);
                         let mut rs = self.traverse(p2, &rs);
-                        for ls in &mut ls {
+                        for (_, ls) in &mut ls {
                             ls.push(PathItem {
                                 first: true,
                                 enter: false,
                                 label,
                             });
                         }
-                        for rs in &mut rs {
+                        for (_, rs) in &mut rs {
                             rs.push(PathItem {
                                 first: false,
                                 enter: false,
@@ -205,10 +239,12 @@ impl Tree {
                         ls
                     }
                     Tree::Var { id } => {
-                        if let Some(e) = self.vars.remove(id) {
-                            vec![e]
+                        let leaf = self.leaves;
+                        self.leaves += 1;
+                        if let Some((first, e)) = self.vars.remove(id) {
+                            vec![((first, leaf), e)]
                         } else {
-                            self.vars.insert(id.clone(), execution.clone());
+                            self.vars.insert(*id, (leaf, execution.clone()));
                             vec![]
                         }
                     }
@@ -218,37 +254,582 @@ impl Tree {
 
         let mut state = State::default();
         let stack = state.traverse(self, &Default::default());
-        let stack: Vec<_> = stack.into_iter().map(|x| x.normal()).collect();
-        let max_len = stack.iter().map(|x| x.neg_len()).fold(
-            BTreeMap::new(),
-            |old: BTreeMap<NodeLabel, usize>, new| {
-                let mut res = BTreeMap::new();
-                for i in old.keys().chain(new.keys()).map(|x| *x).collect::<Vec<_>>() {
-                    let n1 = old.get(&i).cloned().unwrap_or_default();
-                    let n2 = new.get(&i).cloned().unwrap_or_default();
-                    let n = n1.max(n2);
-                    res.insert(i, n);
+        stack.into_iter().map(|(k, x)| (k, x.normal())).collect()
+    }
+    // The paths from the root to each variable and back.
+    fn paths(&self) -> Vec<NormalPathStackSet> {
+        self.leaf_paths().into_iter().map(|x| x.1).collect()
+    }
+    pub fn is_coherent(&self, labels: &LabelTable) -> bool {
+        paths_coherent(&self.paths(), labels)
+    }
+    /// The original coherence check, which pads every path to the same depth
+    /// and compares all of them. Its cost is exponential in that depth, so it
+    /// is only kept as a reference for `is_coherent`.
+    pub fn is_coherent_enumerative(&self, labels: &LabelTable) -> bool {
+        paths_coherent_enumerative(self.paths(), labels)
+    }
+}
+
+impl NormalPathStack {
+    // Whether some padding of the two stacks to the same depth gives them the
+    // same input, and if so, whether it also gives them the same output.
+    // Padding a stack by `b` appends `b` to its input and prepends it to its
+    // output, so if `a`'s input is a prefix of `b`'s, the two agree on every
+    // padding exactly when `b`'s output is the rest of its input, reversed,
+    // followed by `a`'s output.
+    fn unify(&self, other: &Self) -> Option<bool> {
+        let (short, long) = if self.0.len() <= other.0.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        if !long.0.iter().zip(&short.0).all(|(a, b)| a == b) {
+            return None;
+        }
+        let rest = long.0.iter().skip(short.0.len()).rev();
+        Some(long.1.iter().eq(rest.chain(&short.1)))
+    }
+}
+
+// Two paths conflict if they can be padded to the same input on every label
+// but then give different outputs. Transparent labels are ignored, and paths
+// are not padded on commuting ones.
+fn conflict(a: &NormalPathStackSet, b: &NormalPathStackSet, labels: &LabelTable) -> bool {
+    let empty = NormalPathStack::default();
+    let mut agree = true;
+    for label in a.0.keys().chain(b.0.keys()) {
+        if labels.is_transparent(*label) {
+            continue;
+        }
+        let x = a.0.get(label).unwrap_or(&empty);
+        let y = b.0.get(label).unwrap_or(&empty);
+        let unified = match labels.annihilates(*label) {
+            true => x.unify(y),
+            false => (x.0 == y.0).then_some(x.1 == y.1),
+        };
+        match unified {
+            None => return false,
+            Some(same) => agree &= same,
+        }
+    }
+    !agree
+}
+
+fn paths_coherent(paths: &[NormalPathStackSet], labels: &LabelTable) -> bool {
+    paths
+        .iter()
+        .enumerate()
+        .all(|(i, a)| paths[i + 1..].iter().all(|b| !conflict(a, b, labels)))
+}
+
+fn paths_coherent_enumerative(stack: Vec<NormalPathStackSet>, labels: &LabelTable) -> bool {
+    let max_len = stack.iter().map(|x| x.neg_len()).fold(
+        BTreeMap::new(),
+        |old: BTreeMap<NodeLabel, usize>, new| {
+            let mut res = BTreeMap::new();
+            for i in old.keys().chain(new.keys()).copied().collect::<Vec<_>>() {
+                let n1 = old.get(&i).cloned().unwrap_or_default();
+                let n2 = new.get(&i).cloned().unwrap_or_default();
+                let n = n1.max(n2);
+                res.insert(i, n);
+            }
+            res
+        },
+    );
+    let stacks: Vec<_> = stack
+        .into_iter()
+        .map(|mut x| {
+            x.0.retain(|label, _| !labels.is_transparent(*label));
+            x
+        })
+        .flat_map(|x| {
+            // Paths are not padded on commuting labels, since their nodes do
+            // not annihilate with the ones a padding would go through.
+            let mut max_len = max_len.clone();
+            for (label, stack) in &x.0 {
+                if !labels.annihilates(*label) {
+                    max_len.insert(*label, stack.neg_len());
+                }
+            }
+            x.extend_by(&max_len).into_iter()
+        })
+        .map(|x| (x.clone().key(), x))
+        .collect();
+
+    let mut map = BTreeMap::new();
+    for (k, v) in stacks {
+        if map.insert(k, v.clone()).is_some_and(|x| v != x) {
+            return false;
+        }
+    }
+    true
+}
+
+// Where a tree hangs from: the root of the net, a side of a redex, or an
+// auxiliary port of a node.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Place {
+    Top(usize),
+    Port(usize, bool),
+}
+
+#[derive(Clone, Copy)]
+enum Port {
+    Node(usize),
+    Var(DefaultKey),
+}
+
+struct Node {
+    label: NodeLabel,
+    ports: [Port; 2],
+    parent: Place,
+}
+
+// The net as a graph. Top 0 is the root, and tops `2 * i + 1` and `2 * i + 2`
+// are the two sides of redex `i`.
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    tops: Vec<Port>,
+    vars: BTreeMap<DefaultKey, Vec<Place>>,
+    // Variable occurrences in the root tree, in traversal order.
+    root_leaves: BTreeMap<Place, usize>,
+    // The nodes of the root tree come first.
+    root_nodes: usize,
+}
+
+impl Graph {
+    fn new(net: &Net) -> Self {
+        let mut graph = Graph::default();
+        graph.add_top(&net.root);
+        graph.root_nodes = graph.nodes.len();
+        for (a, b) in &net.redexes {
+            graph.add_top(a);
+            graph.add_top(b);
+        }
+        graph
+    }
+    fn add_top(&mut self, tree: &Tree) {
+        let top = self.tops.len();
+        self.tops.push(Port::Node(usize::MAX));
+        self.tops[top] = self.add(tree, Place::Top(top), top == 0);
+    }
+    fn add(&mut self, tree: &Tree, place: Place, in_root: bool) -> Port {
+        match tree {
+            Tree::Binary { label, p1, p2 } => {
+                let n = self.nodes.len();
+                self.nodes.push(Node {
+                    label: *label,
+                    ports: [Port::Node(usize::MAX); 2],
+                    parent: place,
+                });
+                self.nodes[n].ports[0] = self.add(p1, Place::Port(n, true), in_root);
+                self.nodes[n].ports[1] = self.add(p2, Place::Port(n, false), in_root);
+                Port::Node(n)
+            }
+            Tree::Var { id } => {
+                self.vars.entry(*id).or_default().push(place);
+                if in_root {
+                    let index = self.root_leaves.len();
+                    self.root_leaves.insert(place, index);
+                }
+                Port::Var(*id)
+            }
+        }
+    }
+    fn in_root(&self, place: Place) -> bool {
+        match place {
+            Place::Top(top) => top == 0,
+            Place::Port(n, _) => n < self.root_nodes,
+        }
+    }
+    fn port(&self, place: Place) -> Port {
+        match place {
+            Place::Top(top) => self.tops[top],
+            Place::Port(n, first) => self.nodes[n].ports[!first as usize],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Walk {
+    stacks: NormalPathStackSet,
+    // The root variable occurrences through which the walk left and entered
+    // the root tree.
+    left: Option<usize>,
+    entered: Option<usize>,
+}
+
+impl Walk {
+    // Going down through a port of a node pops it from the label's stack, if
+    // the label annihilates. Otherwise the node commutes with the ones the
+    // path came up through, and the port is added to the input.
+    fn enter(&mut self, label: NodeLabel, first: bool, annihilate: bool) -> bool {
+        let stack = self.stacks.0.entry(label).or_default();
+        match stack.1.back() {
+            Some(&top) if annihilate => {
+                stack.1.pop_back();
+                top == first
+            }
+            _ => {
+                stack.0.push_back(first);
+                true
+            }
+        }
+    }
+    fn exit(&mut self, label: NodeLabel, first: bool) {
+        self.stacks.0.entry(label).or_default().1.push_back(first);
+    }
+}
+
+/// Bound on the total length of the paths followed by `Net::paths` outside
+/// the root tree, so that nets without a normal form do not make it loop
+/// forever. Past it, coherence is unknown.
+const MAX_PATH_STEPS: usize = 1 << 16;
+
+impl Net {
+    // Follows every path that starts and ends at the root, going through
+    // redexes as if they were wires between the principal ports of their
+    // trees. Every path is found in both directions, and only the one a
+    // traversal of the normal form would give is kept: the one that leaves the
+    // root tree through an earlier variable, or, through the same one, heads
+    // for the earlier leaf first. Without redexes, this gives the same paths as
+    // `Tree::is_coherent`. `None` if they take more than `MAX_PATH_STEPS` steps
+    // through the redexes, or go through a redex with a custom rule.
+    fn paths(&self) -> Option<Vec<NormalPathStackSet>> {
+        enum Step {
+            Down(Place),
+            Up(Place),
+        }
+        let graph = Graph::new(self);
+        let mut paths = vec![];
+        let mut steps = 0;
+        let start = Walk {
+            stacks: Default::default(),
+            left: None,
+            entered: None,
+        };
+        let mut pending = vec![(Step::Down(Place::Top(0)), start)];
+        while let Some((step, mut walk)) = pending.pop() {
+            let (Step::Down(place) | Step::Up(place)) = step;
+            if !graph.in_root(place) {
+                steps += 1;
+                if steps >= MAX_PATH_STEPS {
+                    return None;
+                }
+            }
+            match step {
+                Step::Down(place) => match graph.port(place) {
+                    Port::Node(n) => {
+                        let label = graph.nodes[n].label;
+                        let annihilate = self.labels.annihilates(label);
+                        for first in [true, false] {
+                            let mut walk = walk.clone();
+                            if walk.enter(label, first, annihilate) {
+                                pending.push((Step::Down(Place::Port(n, first)), walk));
+                            }
+                        }
+                    }
+                    Port::Var(id) => {
+                        let Some(&other) = graph.vars[&id].iter().find(|x| **x != place) else {
+                            continue;
+                        };
+                        if let Some(&leaf) = graph.root_leaves.get(&place) {
+                            walk.left = Some(leaf);
+                        }
+                        if let Some(&leaf) = graph.root_leaves.get(&other) {
+                            walk.entered = Some(leaf);
+                        }
+                        pending.push((Step::Up(other), walk));
+                    }
+                },
+                Step::Up(Place::Port(n, first)) => {
+                    walk.exit(graph.nodes[n].label, first);
+                    pending.push((Step::Up(graph.nodes[n].parent), walk));
+                }
+                Step::Up(Place::Top(0)) => {
+                    if walk.left < walk.entered
+                        || walk.left == walk.entered && walk.stacks.leftward()
+                    {
+                        paths.push(walk.stacks);
+                    }
+                }
+                Step::Up(Place::Top(top)) => {
+                    let other = if top % 2 == 1 { top + 1 } else { top - 1 };
+                    // Where a custom rule takes a path is only known by
+                    // applying it.
+                    if let (Port::Node(a), Port::Node(b)) = (graph.tops[top], graph.tops[other]) {
+                        let (a, b) = (graph.nodes[a].label, graph.nodes[b].label);
+                        if self.labels.rule(a, b).is_some() {
+                            return None;
+                        }
+                    }
+                    pending.push((Step::Down(Place::Top(other)), walk));
+                }
+            }
+        }
+        Some(paths)
+    }
+    /// Checks coherence of the whole net, including its redexes. `None` when
+    /// this is unknown because the paths through the redexes are too long, as
+    /// they may be for nets without a normal form, or go through a pair of
+    /// nodes with a custom rule.
+    pub fn is_coherent(&mut self) -> Option<bool> {
+        self.resolve_vars();
+        if self.redexes.is_empty() {
+            return Some(self.root.is_coherent(&self.labels));
+        }
+        Some(paths_coherent(&self.paths()?, &self.labels))
+    }
+    /// A certificate of the coherence of the net, for `certificate::verify`
+    /// to check, or `None` if it is incoherent. It has one line per variable
+    /// of the root tree: the leaves it connects, in traversal order, and for
+    /// each label, the `l`/`r` inputs and outputs of the path between them.
+    /// Fails if the net is not in normal form.
+    pub fn certificate(&mut self) -> Result<Option<String>, String> {
+        self.resolve_vars();
+        if !self.redexes.is_empty() {
+            return Err("the net is not in normal form".to_string());
+        }
+        if !self.root.is_coherent(&self.labels) {
+            return Ok(None);
+        }
+        let paths = self.root.leaf_paths();
+        let bits = |stack: &VecDeque<bool>| match stack.len() {
+            0 => "-".to_string(),
+            _ => stack.iter().map(|x| if *x { 'l' } else { 'r' }).collect(),
+        };
+        let mut out = String::new();
+        for ((i, j), stacks) in paths {
+            out += &format!("{i} {j}");
+            for (label, stack) in stacks.0 {
+                out += &format!(" {} {} {}", label.0, bits(&stack.0), bits(&stack.1));
+            }
+            out += "\n";
+        }
+        Ok(Some(out))
+    }
+    /// Explains why the net is incoherent, by two of its paths that can start
+    /// the same way but end differently, or `None` if it is coherent. Leaves
+    /// of the root tree are counted from the left, from 0. Fails if the net is
+    /// not in normal form.
+    pub fn incoherence(&mut self) -> Result<Option<String>, String> {
+        self.resolve_vars();
+        if !self.redexes.is_empty() {
+            return Err("the net is not in normal form".to_string());
+        }
+        let show = |path: &NormalPathStackSet| {
+            let stacks = path.0.iter().filter(|x| !self.labels.is_transparent(*x.0));
+            let stacks: Vec<_> = stacks.map(|(k, v)| format!("{}: {v:?}", k.0)).collect();
+            stacks.join(", ")
+        };
+        let paths = self.root.leaf_paths();
+        for (n, ((i, j), a)) in paths.iter().enumerate() {
+            for ((k, l), b) in &paths[n + 1..] {
+                if conflict(a, b, &self.labels) {
+                    return Ok(Some(format!(
+                        "the path between leaves {i} and {j} ({}) conflicts with the one \
+                         between leaves {k} and {l} ({})",
+                        show(a),
+                        show(b)
+                    )));
                 }
-                res
-            },
+            }
+        }
+        Ok(None)
+    }
+    /// Whether the two nets have the same paths from the root back to it,
+    /// ignoring transparent labels. Coherence can not tell such nets apart,
+    /// whatever they are connected to. False when the paths of either are too
+    /// long to follow.
+    pub fn same_paths(&mut self, other: &mut Net) -> bool {
+        let paths = self.observed_paths();
+        paths.is_some() && paths == other.observed_paths()
+    }
+    fn observed_paths(&mut self) -> Option<BTreeSet<NormalPathStackSet>> {
+        self.resolve_vars();
+        let empty = NormalPathStack::default();
+        let mut observed = BTreeSet::new();
+        for mut path in self.paths()? {
+            path.0
+                .retain(|label, stack| !self.labels.is_transparent(*label) && *stack != empty);
+            let reversed = path.reversed();
+            observed.insert(path.min(reversed));
+        }
+        Some(observed)
+    }
+    /// Checks coherence of the root tree only, ignoring any redexes. This
+    /// agrees with `is_coherent` on nets in normal form.
+    pub fn is_root_coherent(&mut self) -> bool {
+        self.resolve_vars();
+        self.root.is_coherent(&self.labels)
+    }
+}
+
+#[test]
+fn coherence_before_normalizing() {
+    let book = crate::parser::TreeParser::new(include_str!("../sample.itt"))
+        .parse_book()
+        .unwrap();
+    for test in &book.tests {
+        let mut net = test.net.clone();
+        let before = net.clone().is_coherent();
+        net.normal(|_| ());
+        assert_eq!(before, net.is_coherent(), "{}", test.name);
+    }
+    let mut bad = book.defs["badtest_1"].clone();
+    assert!(bad.is_root_coherent());
+    assert_eq!(bad.is_coherent(), Some(false));
+}
+
+#[test]
+fn symbolic_matches_enumerative() {
+    let book = crate::parser::TreeParser::new(include_str!("../sample.itt"))
+        .parse_book()
+        .unwrap();
+    for (name, net) in &book.defs {
+        let mut net = net.clone();
+        net.normal(|_| ());
+        net.resolve_vars();
+        let root = &net.root;
+        assert_eq!(
+            root.is_coherent(&net.labels),
+            root.is_coherent_enumerative(&net.labels),
+            "{name}"
         );
-        let stacks: Vec<_> = stack
-            .into_iter()
-            .map(|mut x| {
-                x.0.remove(&NodeLabel::EQL);
-                x
-            })
-            .map(|x| x.extend_by(&max_len).into_iter())
-            .flatten()
-            .map(|x| (x.clone().key(), x))
-            .collect();
-
-        let mut map = BTreeMap::new();
-        for (k, v) in stacks {
-            if map.insert(k, v.clone()).is_some_and(|x| v != x) {
-                return false;
+    }
+    // Enumerated nets, with their nodes relabeled in a few different ways.
+    for depth in 1..=3 {
+        for net in crate::enumerate::Enumerator::new(depth) {
+            for seed in 0..6 {
+                let mut root = net.root.clone();
+                let mut n = 0;
+                root.recurse_mut(&mut |tree| {
+                    if let Tree::Binary { label, .. } = tree {
+                        *label = NodeLabel((n * seed + seed / 2) % 4);
+                        n += 1;
+                    }
+                });
+                let labels = LabelTable::default();
+                assert_eq!(
+                    root.is_coherent(&labels),
+                    root.is_coherent_enumerative(&labels),
+                    "{}",
+                    net.display()
+                );
             }
         }
-        return true;
     }
 }
+
+// A spine of nodes cycling through three labels, like a curried function
+// type, with each argument wired to the one at the mirror position.
+#[cfg(test)]
+fn deep_spine(depth: usize) -> Tree {
+    let mut keys = slotmap::SlotMap::<DefaultKey, ()>::new();
+    let vars: Vec<_> = (0..=depth / 2).map(|_| keys.insert(())).collect();
+    let leaf = |i: usize| Tree::Var {
+        id: vars[i.min(depth - i)],
+    };
+    let mut tree = leaf(depth);
+    for i in (0..depth).rev() {
+        tree = Tree::Binary {
+            label: NodeLabel(i as u64 % 3),
+            p1: Box::new(leaf(i)),
+            p2: Box::new(tree),
+        };
+    }
+    tree
+}
+
+#[cfg(test)]
+const DEPTH: usize = 17;
+
+#[bench]
+fn bench_symbolic(b: &mut test::Bencher) {
+    let tree = deep_spine(DEPTH);
+    b.iter(|| assert!(tree.is_coherent(&LabelTable::default())));
+}
+
+#[bench]
+fn bench_enumerative(b: &mut test::Bencher) {
+    let tree = deep_spine(DEPTH);
+    b.iter(|| assert!(tree.is_coherent_enumerative(&LabelTable::default())));
+}
+
+#[test]
+fn coherence_is_invariant() {
+    let mut net = crate::parser::TreeParser::new(
+        "[x0 x1] & (([x2 x3] <x4 x5>) [(x2 x3) (x4 x5)]) = [x6 x1] & <x7 x7> = <x6 x0>",
+    )
+    .parse_net()
+    .unwrap();
+    let mut normal = net.clone();
+    normal.normal(|_| ());
+    assert_eq!(net.is_coherent(), normal.is_coherent());
+    for net in crate::enumerate::Enumerator::new(3) {
+        let mut normal = net.clone();
+        if normal.normal_within(crate::equiv::MAX_INTERACTIONS) {
+            assert_eq!(
+                net.clone().is_coherent(),
+                normal.is_coherent(),
+                "{}",
+                net.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn coherence_without_normal_form() {
+    let book = crate::book::Book::parse("term λz (z (λx (x x) λy (y y)))").unwrap();
+    assert_eq!(book.root.clone().is_coherent(), None);
+}
+
+#[test]
+fn deep_normal_nets_are_coherent() {
+    fn balanced(leaves: &mut std::ops::RangeFrom<usize>, depth: usize) -> String {
+        if depth == 0 {
+            let v = leaves.next().unwrap();
+            return format!("(v{v} v{v})");
+        }
+        let p1 = balanced(leaves, depth - 1);
+        format!("({p1} {})", balanced(leaves, depth - 1))
+    }
+    let nested = (0..300).rev().fold("(w w)".to_string(), |tree, v| {
+        format!("((v{v} v{v}) {tree})")
+    });
+    for source in [nested, balanced(&mut (0..), 11)] {
+        let mut net = crate::parser::TreeParser::new(&source).parse_net().unwrap();
+        assert_eq!(net.is_coherent(), Some(true));
+        let certificate = net.certificate().unwrap().unwrap();
+        assert_eq!(crate::certificate::verify(&net, &certificate), Ok(()));
+    }
+}
+
+#[test]
+fn commuting_labels() {
+    for (semantics, coherent) in [("annihilate", true), ("commute", false)] {
+        let src = format!("label 5 {semantics}\n([a b] [c d]) & {{5 a b}} = {{5 c d}}");
+        let book = crate::book::Book::parse(&src).unwrap();
+        let mut net = book.root.clone();
+        assert_eq!(net.clone().is_coherent(), Some(coherent), "{semantics}");
+        net.normal(|_| ());
+        assert_eq!(net.is_coherent(), Some(coherent), "{semantics}");
+        assert_eq!(crate::goi::oracle(&book.root), Ok(()), "{semantics}");
+    }
+}
+
+#[test]
+fn custom_rules_are_unknown() {
+    let src = "rule 5 ~ 6 => a1 = b2 & a2 = b1\n([a b] [c d]) & {5 a b} = {6 c d}";
+    let book = crate::book::Book::parse(src).unwrap();
+    let mut net = book.root.clone();
+    assert_eq!(net.clone().is_coherent(), None);
+    assert!(crate::goi::oracle(&net)
+        .unwrap_err()
+        .contains("custom rule"));
+    net.normal(|_| ());
+    assert_eq!(net.is_coherent(), Some(true));
+}
@@ -0,0 +1,67 @@
+//! Checks empirically that the normal form of a net does not depend on the
+//! order in which its redexes are reduced.
+use crate::{equiv::MAX_INTERACTIONS, run::Strategy, tree::Net};
+
+/// Random orders tried besides the deterministic ones.
+pub const SEEDS: u64 = 16;
+
+/// The normal forms of a net under several orders.
+#[derive(Debug)]
+pub struct Report {
+    pub orders: usize,
+    /// The first order whose normal form differs from the LIFO one, with
+    /// both normal forms.
+    pub divergence: Option<(Strategy, Net, Net)>,
+    /// Whether all normal forms are coherent, or none is.
+    pub coherence_agrees: bool,
+}
+
+impl Report {
+    pub fn confluent(&self) -> bool {
+        self.divergence.is_none() && self.coherence_agrees
+    }
+}
+
+/// Normalizes `net` with LIFO, FIFO and `seeds` random orders, and compares
+/// their normal forms. Fails if the net does not normalize within
+/// `MAX_INTERACTIONS`.
+pub fn confluence(net: &Net, seeds: u64) -> Result<Report, String> {
+    if !net.clone().normal_within(MAX_INTERACTIONS) {
+        return Err(format!(
+            "does not normalize within {MAX_INTERACTIONS} interactions"
+        ));
+    }
+    let normal = |strategy| {
+        let mut normal = net.clone();
+        normal.normal_with(strategy, |_| ());
+        normal.resolve_vars();
+        let coherent = normal.is_coherent();
+        (normal, coherent)
+    };
+    let (reference, coherent) = normal(Strategy::Lifo);
+    let mut report = Report {
+        orders: 1,
+        divergence: None,
+        coherence_agrees: true,
+    };
+    let strategies = [Strategy::Fifo].into_iter();
+    for strategy in strategies.chain((0..seeds).map(Strategy::Random)) {
+        let (other, other_coherent) = normal(strategy);
+        report.orders += 1;
+        report.coherence_agrees &= other_coherent == coherent;
+        if report.divergence.is_none() && !reference.alpha_eq(&other) {
+            report.divergence = Some((strategy, reference.clone(), other));
+        }
+    }
+    Ok(report)
+}
+
+#[test]
+fn sample_is_confluent() {
+    let book = crate::book::Book::parse(include_str!("../sample.itt")).unwrap();
+    for (name, net) in &book.defs {
+        let report = confluence(net, 4).unwrap();
+        assert_eq!(report.orders, 6);
+        assert!(report.confluent(), "{name}");
+    }
+}
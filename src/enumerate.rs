@@ -1,108 +1,125 @@
-//! Generates trees in this order:
+//! Enumerates closed nets made of a complete tree of `CON` nodes, in every way
+//! of wiring its leaves together. For depth 2 these are, in order:
 // ((a a) (b b))
 // ((a b) (a b))
-
-// 00 -> 00 -> 00 a
-// 01 -> 01 -> 01 a
-// 10 -> 10 -> 11 b
-// 11 -> 11 -> 10 b
+// ((a b) (b a))
 //
-// 00 -> 00 -> 00 a 
-// 01 -> 10 -> 11 b
-// 10 -> 01 -> 01 a
-// 11 -> 11 -> 10 b
-
-// Odd amount of 1s: +
-// Even amount of 1s: -
+// The index of a net is read as a mixed-radix number: the first free leaf is
+// wired to the `index % n`th of the `n` other free leaves, then the next free
+// leaf to the `(index / n) % (n - 2)`th of the remaining ones, and so on.
 
 use slotmap::{DefaultKey, SlotMap};
 
-use crate::tree::{Net, NodeLabel, Tree};
+use crate::{
+    book::Book,
+    tree::{Net, NodeLabel, Tree},
+};
 
 pub struct Enumerator {
-	depth: u64,
-	index: u64,
+    depth: u64,
+    index: u64,
 }
 
 impl Enumerator {
-	fn factorial(num: u64) -> u64 {
-		if num == 0 {
-			1
-		} else {
-			Self::factorial(num - 1) * num
-		}
-	}
-	fn bits_amount(&self) -> u64 {
-		self.depth
-	}
-	fn wire_amount(&self) -> u64 {
-		2u64.pow(self.depth as u32 - 1)
-	}
-	fn index_amount(&self) -> u64 {
-		Self::factorial(self.wire_amount())
-	}
-	fn permute(&self, mut bits: u64) -> u64 {
-		let mut index = self.index;
-		let mut result = 0;
-		for i in (0..self.bits_amount()).rev() {
-			let fact = Self::factorial(i);
-			let q = index.div_floor(fact);
-			index = index.rem_euclid(fact);
-			result |= (bits >> q) & 1;
-			let left_bits = bits >> (q + 1);
-			let right_bits = bits & ((1 << q) - 1);
-			let new_bits = (left_bits << q) | right_bits;
-			//println!("{q} {bits:0>2b} {left_bits:0>2b} {right_bits:0>2b} {new_bits:0>2b}", );
-			bits = new_bits;
-			result <<= 1;
-		}
-		result >> 1
-	}
-	fn generate(&mut self, vars: &[DefaultKey], depth: u64, path: u64) -> Tree {
-		if depth == 0 {
-			let var_id = self.permute(path);
-			let var_id = if var_id.count_ones() & 1 != 0 {
-				((var_id >> 1) << 1) | 1
-			} else {
-				((var_id >> 1) << 1) | 0
-			} >> 1;
-			Tree::Var {
-				id: vars[var_id as usize].clone(),
-			}
-		} else {
-			Tree::Binary {
-				label: NodeLabel::CON,
-				p1: Box::new(self.generate(vars, depth - 1, path << 1)),
-				p2: Box::new(self.generate(vars, depth - 1, path << 1 | 1)),
-			}
-		}
-	}
-	fn gen_all(&mut self) {
-		for i in 0..self.index_amount() {
-			println!("{}", self.next().display());
-		}
-	}
-	fn next(&mut self) -> Net {
-		let mut vars = SlotMap::new();
-		let mut var_idxs = vec![];
-		for i in 0..self.wire_amount() {
-			var_idxs.push(vars.insert(None));
-		}
-		let root = self.generate(&var_idxs, self.depth, 0);
-		self.index += 1;
-		Net {
-		    root: root,
-		    redexes: vec![],
-		    vars,
-		}
-	}
+    pub fn new(depth: u64) -> Self {
+        assert!(depth > 0, "a single leaf can not be wired");
+        Enumerator { depth, index: 0 }
+    }
+    fn leaf_amount(&self) -> u64 {
+        1 << self.depth
+    }
+    fn wire_amount(&self) -> u64 {
+        self.leaf_amount() / 2
+    }
+    pub fn index_amount(&self) -> u64 {
+        (0..self.wire_amount()).map(|i| 2 * i + 1).product()
+    }
+    // The wire each leaf is connected to.
+    fn wiring(&self) -> Vec<usize> {
+        let mut index = self.index;
+        let mut free: Vec<usize> = (0..self.leaf_amount() as usize).collect();
+        let mut wiring = vec![0; free.len()];
+        let mut wire = 0;
+        while !free.is_empty() {
+            let first = free.remove(0);
+            let n = free.len() as u64;
+            let other = free.remove((index % n) as usize);
+            index /= n;
+            wiring[first] = wire;
+            wiring[other] = wire;
+            wire += 1;
+        }
+        wiring
+    }
+    fn generate(&self, vars: &[DefaultKey], wiring: &[usize], depth: u64, path: usize) -> Tree {
+        if depth == 0 {
+            Tree::Var {
+                id: vars[wiring[path]],
+            }
+        } else {
+            Tree::Binary {
+                label: NodeLabel::CON,
+                p1: Box::new(self.generate(vars, wiring, depth - 1, path << 1)),
+                p2: Box::new(self.generate(vars, wiring, depth - 1, (path << 1) | 1)),
+            }
+        }
+    }
+}
+
+impl Iterator for Enumerator {
+    type Item = Net;
+
+    fn next(&mut self) -> Option<Net> {
+        if self.index >= self.index_amount() {
+            return None;
+        }
+        let mut vars = SlotMap::new();
+        let mut var_idxs = vec![];
+        for _ in 0..self.wire_amount() {
+            var_idxs.push(vars.insert(None));
+        }
+        let root = self.generate(&var_idxs, &self.wiring(), self.depth, 0);
+        self.index += 1;
+        Some(Net {
+            root,
+            redexes: Default::default(),
+            vars,
+            names: Default::default(),
+            holes: Default::default(),
+            labels: Default::default(),
+        })
+    }
+}
+
+/// Closed nets to try where a term is expected: the definitions of the book
+/// that are not tests and have no holes, then the enumerated nets up to
+/// `depth`, each with its name or display.
+pub fn candidates(book: &Book, depth: u64) -> impl Iterator<Item = (String, Net)> + '_ {
+    let defs = book
+        .defs
+        .iter()
+        .filter(|(name, net)| net.holes.is_empty() && book.tests.iter().all(|x| &x.name != *name))
+        .map(|(name, net)| (name.clone(), net.clone()));
+    let enumerated = (1..=depth).flat_map(Enumerator::new).map(|mut net| {
+        net.labels = book.labels.clone();
+        (net.display(), net)
+    });
+    defs.chain(enumerated)
 }
 
 #[test]
 fn test() {
-	let mut a = Enumerator {
-	    depth: 3,
-	    index: 0,
-	};
-	a.gen_all();
-}
\ No newline at end of file
+    let nets: Vec<_> = Enumerator::new(2).map(|x| x.display()).collect();
+    assert_eq!(
+        nets,
+        [
+            "((x0 x0) (x1 x1))",
+            "((x0 x1) (x0 x1))",
+            "((x0 x1) (x1 x0))"
+        ]
+    );
+    let nets: std::collections::HashSet<_> = Enumerator::new(3)
+        .inspect(|x| x.validate().unwrap())
+        .collect();
+    assert_eq!(nets.len(), 105);
+}
@@ -0,0 +1,84 @@
+//! Observational equivalence of types: two types are equivalent when every
+//! term checks against both of them or against neither.
+use crate::{book::Book, enumerate::candidates, tree::Net};
+
+/// How deep the enumerated terms of the bounded search go. Depth 4 already
+/// has millions of nets.
+pub const MAX_DEPTH: u64 = 3;
+
+/// Terms whose check takes more interactions than this to normalize are
+/// skipped, since they may have no normal form.
+pub const MAX_INTERACTIONS: usize = 1 << 16;
+
+#[derive(Debug)]
+pub enum Verdict {
+    /// The normal forms of the two types have the same paths, so no term can
+    /// tell them apart.
+    Equivalent,
+    /// None of the `tried` terms tells the types apart, but larger ones, or
+    /// the ones without a normal form, might.
+    NoDifference { tried: usize },
+    /// `witness` checks against exactly one of the types, the first one if
+    /// `first` is set.
+    Distinguished { witness: String, first: bool },
+}
+
+fn checks(term: &Net, ty: &Net) -> Option<bool> {
+    let mut net = Net::check(term.clone(), ty.clone());
+    net.normal_within(MAX_INTERACTIONS)
+        .then(|| net.is_coherent())
+        .flatten()
+}
+
+/// Decides whether the definitions `a` and `b` of the book are equivalent as
+/// types. When their paths differ, this falls back to checking the closed
+/// definitions of the book and the enumerated nets up to `depth` against
+/// both.
+pub fn equiv(book: &Book, a: &str, b: &str, depth: u64) -> Result<Verdict, String> {
+    let get = |name: &str| {
+        let mut net = book
+            .defs
+            .get(name)
+            .ok_or(format!("no definition `{name}`"))?
+            .clone();
+        net.validate()?;
+        net.normal(|_| ());
+        Ok::<_, String>(net)
+    };
+    let (mut a, mut b) = (get(a)?, get(b)?);
+    if a.alpha_eq(&b) || a.same_paths(&mut b) {
+        return Ok(Verdict::Equivalent);
+    }
+
+    let mut tried = 0;
+    for (witness, term) in candidates(book, depth) {
+        let (Some(first), Some(second)) = (checks(&term, &a), checks(&term, &b)) else {
+            continue;
+        };
+        tried += 1;
+        if first != second {
+            return Ok(Verdict::Distinguished { witness, first });
+        }
+    }
+    Ok(Verdict::NoDifference { tried })
+}
+
+#[test]
+fn equivalent_types() {
+    use crate::parser::TreeParser;
+    let book = TreeParser::new(
+        "def Unit = <(x x) (y y)>
+         def Unit2 = <([a b] [b a]) (y y)>
+         def Pair = <(x y) (x y)>
+         (a a)",
+    )
+    .parse_book()
+    .unwrap();
+    let equiv = |a, b| equiv(&book, a, b, MAX_DEPTH);
+    assert!(matches!(equiv("Unit", "Unit2"), Ok(Verdict::Equivalent)));
+    assert!(matches!(
+        equiv("Unit", "Pair"),
+        Ok(Verdict::Distinguished { first: false, .. })
+    ));
+    assert!(equiv("Unit", "Nat").is_err());
+}
@@ -0,0 +1,99 @@
+//! Fills the holes of a net by trying candidate terms in each of them, and
+//! keeping the combinations that make the net coherent.
+use crate::{
+    book::Book,
+    enumerate::candidates,
+    equiv::MAX_INTERACTIONS,
+    tree::{Net, Tree},
+};
+
+/// How deep the enumerated candidates go. Every combination of candidates is
+/// tried, so this is kept small.
+pub const MAX_DEPTH: u64 = 2;
+
+/// Bound on the number of combinations of candidates tried, past which the
+/// search gives up.
+pub const MAX_COMBINATIONS: usize = 1 << 12;
+
+#[derive(Debug)]
+pub struct Solution {
+    /// The candidate put in each hole, by name.
+    pub fills: Vec<(String, String)>,
+    /// The number of nodes of the candidates.
+    pub size: usize,
+}
+
+fn size(net: &Net) -> usize {
+    let mut size = 0;
+    net.recurse_ref(&mut |tree| size += matches!(tree, Tree::Binary { .. }) as usize);
+    size
+}
+
+/// The combinations of candidates that make the net coherent when put in its
+/// holes, smallest first. Fails if there are more than `MAX_COMBINATIONS`.
+pub fn fill(book: &Book, net: &Net, depth: u64) -> Result<Vec<Solution>, String> {
+    let candidates: Vec<_> = candidates(book, depth)
+        .map(|(name, net)| (size(&net), name, net))
+        .collect();
+    let holes: Vec<_> = net.holes.keys().cloned().collect();
+    let mut solutions = vec![];
+    // Picks a candidate for each hole, in order, as a mixed-radix number.
+    let total = candidates
+        .len()
+        .checked_pow(holes.len() as u32)
+        .filter(|x| *x <= MAX_COMBINATIONS)
+        .ok_or(format!(
+            "search space too large: {} candidates for {} holes",
+            candidates.len(),
+            holes.len()
+        ))?;
+    for mut index in 0..total {
+        let mut filled = net.clone();
+        let mut solution = Solution {
+            fills: vec![],
+            size: 0,
+        };
+        for hole in &holes {
+            let (size, name, candidate) = &candidates[index % candidates.len()];
+            index /= candidates.len();
+            let tree = filled.holes.remove(hole).unwrap();
+            let root = filled.inject(candidate.clone());
+            filled.redexes.push_back((root, tree));
+            solution.fills.push((hole.clone(), name.clone()));
+            solution.size += size;
+        }
+        if filled.normal_within(MAX_INTERACTIONS) && filled.is_coherent() == Some(true) {
+            solutions.push(solution);
+        }
+    }
+    solutions.sort_by_key(|x| x.size);
+    Ok(solutions)
+}
+
+#[test]
+fn fill_holes() {
+    let book = crate::parser::TreeParser::new(
+        "def Bool = <((a b) (c d)) ((d c) (b a))>
+         def Unit = <(x x) (y y)>
+         def Bool.true = ((a a) (b b))
+         check ?h : Unit
+         (a a)",
+    )
+    .parse_book()
+    .unwrap();
+    let solutions = fill(&book, &book.tests[0].net, MAX_DEPTH).unwrap();
+    let fills: Vec<_> = solutions.iter().map(|x| x.fills[0].1.as_str()).collect();
+    assert!(fills.contains(&"(x0 x0)"));
+    assert!(!fills.contains(&"Bool.true"));
+    assert!(solutions.windows(2).all(|x| x[0].size <= x[1].size));
+
+    let holes: Vec<_> = (0..20).map(|i| format!("?h{i}")).collect();
+    let book = crate::parser::TreeParser::new(&format!(
+        "def Unit = <(x x) (y y)>\ncheck ({}) : Unit\n(a a)",
+        holes.join(" ")
+    ))
+    .parse_book()
+    .unwrap();
+    let error = fill(&book, &book.tests[0].net, MAX_DEPTH).unwrap_err();
+    assert!(error.contains("search space too large"), "{error}");
+}
@@ -0,0 +1,409 @@
+//! Formats the source of books in a consistent style. Works on tokens rather
+//! than on parsed nets, so that term notation, names and `##` comments stay
+//! as written, while spacing is normalized and long lines are broken.
+use crate::{book::Book, pretty::Doc};
+
+pub const WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open(String),
+    Close(String),
+    /// Names, numbers, `#label`s, `?hole`s and quoted strings.
+    Word(String),
+    Symbol(&'static str),
+    Comment(String),
+}
+
+struct Lexeme {
+    token: Token,
+    /// Newlines between the previous token and this one.
+    lines_before: usize,
+}
+
+const SYMBOLS: [&str; 11] = ["==", "=>", "~>", "=", "~", "&", ":", ";", "λ", "@", "θ"];
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_.-/$".contains(c)
+}
+
+fn closing(open: &str) -> String {
+    match open {
+        "(" => ")",
+        "[" => "]",
+        "<" => ">",
+        _ => "}",
+    }
+    .to_string()
+}
+
+fn show(token: &Token) -> String {
+    match token {
+        Token::Open(s) | Token::Close(s) | Token::Word(s) | Token::Comment(s) => s.clone(),
+        Token::Symbol(s) => s.to_string(),
+    }
+}
+
+// Splits the source into tokens, taking the delimiters declared for labels,
+// longest first, as brackets too.
+fn tokenize(source: &str, delimiters: &[(String, String)]) -> Result<Vec<Lexeme>, String> {
+    let mut lexemes = vec![];
+    let mut lines_before = 0;
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        let name_len = |s: &str| s.find(|c| !is_name_char(c)).unwrap_or(s.len());
+        let declared = delimiters
+            .iter()
+            .flat_map(|(open, close)| [Token::Open(open.clone()), Token::Close(close.clone())]);
+        let declared = declared
+            .filter(|x| rest.starts_with(&show(x)))
+            .max_by_key(|x| show(x).len());
+        let (token, len) = if c.is_whitespace() {
+            lines_before += (c == '\n') as usize;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        } else if rest.starts_with("##") {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            (Token::Comment(rest[..len].trim_end().to_string()), len)
+        } else if let Some(token) = declared {
+            let len = show(&token).len();
+            (token, len)
+        } else if "([<{".contains(c) {
+            (Token::Open(c.to_string()), 1)
+        } else if ")]>}".contains(c) {
+            (Token::Close(c.to_string()), 1)
+        } else if c == '#' || c == '?' {
+            let len = 1 + name_len(&rest[1..]);
+            (Token::Word(rest[..len].to_string()), len)
+        } else if c == '"' {
+            let mut escaped = false;
+            let end = rest[1..].find(|c| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            });
+            let len = 2 + end.ok_or("unterminated string")?;
+            (Token::Word(rest[..len].to_string()), len)
+        } else if is_name_char(c) {
+            let len = name_len(rest);
+            (Token::Word(rest[..len].to_string()), len)
+        } else if let Some(symbol) = SYMBOLS.into_iter().find(|x| rest.starts_with(x)) {
+            (Token::Symbol(symbol), symbol.len())
+        } else {
+            return Err(format!("unexpected `{c}`"));
+        };
+        lexemes.push(Lexeme {
+            token,
+            lines_before,
+        });
+        lines_before = 0;
+        rest = &rest[len..];
+    }
+    Ok(lexemes)
+}
+
+struct Formatter {
+    lexemes: Vec<Lexeme>,
+    index: usize,
+    delimiters: Vec<(String, String)>,
+}
+
+impl Formatter {
+    // The next token that is not a comment.
+    fn peek(&self) -> Option<&Token> {
+        let mut tokens = self.lexemes[self.index..].iter().map(|x| &x.token);
+        tokens.find(|x| !matches!(x, Token::Comment(_)))
+    }
+    // Takes the next token, along with the comments before it, laid out on
+    // their own lines.
+    fn next(&mut self) -> Result<(Doc, Token), String> {
+        let mut comments = vec![];
+        loop {
+            let Some(lexeme) = self.lexemes.get(self.index) else {
+                return Err("unexpected end of file".to_string());
+            };
+            self.index += 1;
+            match &lexeme.token {
+                Token::Comment(text) => comments.extend([Doc::text(text), Doc::HardLine]),
+                token => return Ok((Doc::Concat(comments), token.clone())),
+            }
+        }
+    }
+    // Takes the next token, which must satisfy `expected`.
+    fn take(&mut self, expected: impl Fn(&Token) -> bool) -> Result<Doc, String> {
+        let (comments, token) = self.next()?;
+        if !expected(&token) {
+            return Err(format!("unexpected `{}`", show(&token)));
+        }
+        Ok(Doc::Concat(vec![comments, Doc::text(show(&token))]))
+    }
+    fn symbol(&mut self, symbol: &'static str) -> Result<Doc, String> {
+        self.take(|x| *x == Token::Symbol(symbol))
+    }
+    fn word(&mut self) -> Result<Doc, String> {
+        self.take(|x| matches!(x, Token::Word(_)))
+    }
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(x)) if x == word)
+    }
+    fn tree(&mut self) -> Result<Doc, String> {
+        let (comments, token) = self.next()?;
+        let doc = match token {
+            Token::Open(open) => {
+                let declared = self.delimiters.iter().find(|x| x.0 == open);
+                let close = declared.map(|x| x.1.clone());
+                let mut items = vec![];
+                if close.is_none() && open == "{" {
+                    items.push(self.word()?);
+                }
+                items.push(self.tree()?);
+                items.push(self.tree()?);
+                let closing = Token::Close(close.unwrap_or_else(|| closing(&open)));
+                let close = self.take(|x| *x == closing)?;
+                Doc::Concat(vec![Doc::delimited(&open, items, ""), close])
+            }
+            Token::Word(word) if word == "term" => {
+                Doc::Concat(vec![Doc::text("term "), self.term()?])
+            }
+            Token::Word(word) => Doc::text(word),
+            token => return Err(format!("unexpected `{}`", show(&token))),
+        };
+        Ok(Doc::Concat(vec![comments, doc]))
+    }
+    fn term(&mut self) -> Result<Doc, String> {
+        let (comments, token) = self.next()?;
+        let doc = match token {
+            Token::Word(label) if label.starts_with('#') => {
+                Doc::Concat(vec![Doc::text(label + " "), self.term()?])
+            }
+            Token::Symbol(binder @ ("λ" | "@" | "θ")) => {
+                let pattern = self.term()?;
+                let body = self.term()?;
+                Doc::group(Doc::Concat(vec![
+                    Doc::text(binder),
+                    pattern,
+                    Doc::nest(2, Doc::Concat(vec![Doc::Line, body])),
+                ]))
+            }
+            Token::Open(open) if ["(", "{", "<"].contains(&open.as_str()) => {
+                let closing = Token::Close(closing(&open));
+                let mut items = vec![self.term()?];
+                loop {
+                    match self.peek() {
+                        Some(token) if *token == closing => break,
+                        // <x: A>
+                        Some(Token::Symbol(":")) => {
+                            let colon = self.symbol(":")?;
+                            let last = items.pop().unwrap();
+                            items.push(Doc::Concat(vec![last, colon]));
+                            items.push(self.term()?);
+                        }
+                        // <a == b>
+                        Some(Token::Symbol("==")) => {
+                            let equals = self.symbol("==")?;
+                            let term = self.term()?;
+                            items.push(Doc::Concat(vec![equals, Doc::text(" "), term]));
+                        }
+                        Some(_) => items.push(self.term()?),
+                        None => return Err("unexpected end of file".to_string()),
+                    }
+                }
+                let close = self.take(|x| *x == closing)?;
+                Doc::Concat(vec![Doc::delimited(&open, items, ""), close])
+            }
+            // let pattern = value; next
+            Token::Word(word) if word == "let" => {
+                let pattern = self.term()?;
+                let equals = self.symbol("=")?;
+                let value = self.term()?;
+                let semicolon = self.symbol(";")?;
+                let next = self.term()?;
+                Doc::group(Doc::Concat(vec![
+                    Doc::text("let "),
+                    pattern,
+                    Doc::text(" "),
+                    equals,
+                    Doc::text(" "),
+                    value,
+                    semicolon,
+                    Doc::Line,
+                    next,
+                ]))
+            }
+            Token::Word(word) if word == "tree" => {
+                Doc::Concat(vec![Doc::text("tree "), self.tree()?])
+            }
+            Token::Word(word) => Doc::text(word),
+            token => return Err(format!("unexpected `{}`", show(&token))),
+        };
+        Ok(Doc::Concat(vec![comments, doc]))
+    }
+    // A root tree, then one line per redex when broken.
+    fn net(&mut self) -> Result<Doc, String> {
+        let root = self.tree()?;
+        let mut redexes = vec![];
+        while self.peek() == Some(&Token::Symbol("&")) {
+            let and = self.symbol("&")?;
+            let a = self.tree()?;
+            let equals = self.symbol("=")?;
+            let b = self.tree()?;
+            redexes.extend([
+                Doc::Line,
+                and,
+                Doc::text(" "),
+                a,
+                Doc::text(" "),
+                equals,
+                Doc::text(" "),
+                b,
+            ]);
+        }
+        Ok(Doc::group(Doc::Concat(vec![
+            root,
+            Doc::nest(2, Doc::Concat(redexes)),
+        ])))
+    }
+    fn statement(&mut self) -> Result<Doc, String> {
+        let mut docs = vec![];
+        let space = || Doc::text(" ");
+        if self.peek_word("def") {
+            // def [test] name = net [~> net]
+            docs.extend([self.word()?, space()]);
+            if self.peek_word("test") {
+                docs.extend([self.word()?, space()]);
+            }
+            docs.extend([self.word()?, space()]);
+            docs.extend([self.symbol("=")?, space()]);
+            docs.push(self.net()?);
+            if self.peek() == Some(&Token::Symbol("~>")) {
+                let arrow = self.symbol("~>")?;
+                let expected = self.net()?;
+                docs.push(Doc::group(Doc::nest(
+                    2,
+                    Doc::Concat(vec![Doc::Line, arrow, Doc::text(" "), expected]),
+                )));
+            }
+        } else if self.peek_word("check") {
+            // check [not] term : type
+            docs.extend([self.word()?, space()]);
+            if self.peek_word("not") {
+                docs.extend([self.word()?, space()]);
+            }
+            docs.extend([self.term()?, space()]);
+            docs.extend([self.symbol(":")?, space()]);
+            docs.push(self.term()?);
+        } else if self.peek_word("rule") {
+            // rule label ~ label => tree = tree & ...
+            docs.extend([self.word()?, space()]);
+            docs.extend([self.word()?, space()]);
+            docs.extend([self.symbol("~")?, space()]);
+            docs.extend([self.word()?, space()]);
+            docs.push(self.symbol("=>")?);
+            let mut links = vec![Doc::Line];
+            loop {
+                links.extend([self.tree()?, space(), self.symbol("=")?]);
+                links.extend([space(), self.tree()?]);
+                if self.peek() != Some(&Token::Symbol("&")) {
+                    break;
+                }
+                links.extend([Doc::Line, self.symbol("&")?, space()]);
+            }
+            docs.push(Doc::group(Doc::nest(2, Doc::Concat(links))));
+        } else if self.peek_word("label") {
+            // label label [transparent | opaque] [annihilate | commute] ["open" "close"]
+            docs.extend([self.word()?, space()]);
+            docs.push(self.word()?);
+            let semantics = ["transparent", "opaque", "annihilate", "commute"];
+            while let Some(Token::Word(word)) = self.peek() {
+                if !semantics.contains(&word.as_str()) && !word.starts_with('"') {
+                    break;
+                }
+                docs.extend([space(), self.word()?]);
+            }
+        } else {
+            docs.push(self.net()?);
+        }
+        Ok(Doc::Concat(docs))
+    }
+}
+
+/// The source of a book in the standard style. Fails if the source is not a
+/// valid book.
+pub fn format(source: &str) -> Result<String, String> {
+    let book = Book::parse(source).map_err(|e| e.to_string())?;
+    let delimiters: Vec<_> = book
+        .labels
+        .delimiters()
+        .map(|(_, open, close)| (open.to_string(), close.to_string()))
+        .collect();
+    let mut formatter = Formatter {
+        lexemes: tokenize(source, &delimiters)?,
+        index: 0,
+        delimiters,
+    };
+    let mut out = String::new();
+    while let Some(lexeme) = formatter.lexemes.get(formatter.index) {
+        // Comments after a statement stay on its line; blank lines between
+        // statements and comments are kept, but not repeated.
+        if !out.is_empty() {
+            match lexeme.lines_before {
+                0 if matches!(lexeme.token, Token::Comment(_)) => out.push(' '),
+                0 | 1 => out.push('\n'),
+                _ => out += "\n\n",
+            }
+        }
+        if let Token::Comment(text) = &lexeme.token {
+            out += text;
+            formatter.index += 1;
+        } else {
+            out += &formatter.statement()?.render(WIDTH);
+        }
+    }
+    out.push('\n');
+    Book::parse(&out).map_err(|e| format!("formatting produced an invalid book: {e}"))?;
+    Ok(out)
+}
+
+#[test]
+fn formats_books() {
+    let source = "## Booleans
+def Bool.true=((a a)(b   b)) ## true
+
+
+def Bool.id = term λx(x  x) ## not quite
+def test  id_true = term (Bool.id Bool.true)
+  ~> Bool.true
+check   not λx <x :Bool.id> : Bool.id
+def Long = (((long_name_a long_name_b) (long_name_c long_name_d)) ((long_name_d long_name_b) (long_name_c long_name_a)))
+(a b) & (x ## a wire
+  x) = (a b)
+";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "## Booleans
+def Bool.true = ((a a) (b b)) ## true
+
+def Bool.id = term λx (x x) ## not quite
+def test id_true = term (Bool.id Bool.true) ~> Bool.true
+check not λx <x: Bool.id> : Bool.id
+def Long = (((long_name_a long_name_b) (long_name_c long_name_d))
+  ((long_name_d long_name_b) (long_name_c long_name_a)))
+(a b)
+  & (x
+    ## a wire
+    x) = (a b)
+"
+    );
+    assert_eq!(format(&formatted).unwrap(), formatted);
+    let declared = "label 5 commute \"⟨\" \"⟩\"\n(⟨a  b⟩ {5 a b})";
+    assert_eq!(
+        format(declared).unwrap(),
+        "label 5 commute \"⟨\" \"⟩\"\n(⟨a b⟩ {5 a b})\n"
+    );
+    let sample = include_str!("../sample.itt");
+    assert_eq!(
+        format(&format(sample).unwrap()).unwrap(),
+        format(sample).unwrap()
+    );
+}
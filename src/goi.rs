@@ -0,0 +1,338 @@
+//! A geometry of interaction token machine, used as a reference semantics for
+//! normalization and coherence. A token leaves the root carrying a stack of
+//! `l`/`r` choices for each label, and travels through the net: entering a
+//! node through its principal port pops the choice of auxiliary port, and
+//! entering it through an auxiliary port pushes which one it was. Redexes are
+//! plain wires between the principal ports of their trees. Nodes with a
+//! commuting label push onto a separate stack, since they are not undone by
+//! the next node of that label the token meets.
+//!
+//! The stacks a token needs are found by running it with empty ones, and
+//! whenever it runs out, running it again with both choices added at the
+//! bottom. The paths of a net are then the stacks each run consumed and
+//! what it left on its stacks when it got back to the root. Two paths
+//! conflict if tokens sent out again with the same opaque choices, enough
+//! for both, come back differently.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use slotmap::DefaultKey;
+
+use crate::{
+    certificate::Path,
+    equiv::MAX_INTERACTIONS,
+    labels::LabelTable,
+    tree::{Net, NodeLabel, Tree},
+};
+
+/// Bound on the total number of steps taken by tokens in a net, since they
+/// may never come back in nets without a normal form.
+const MAX_STEPS: usize = 1 << 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum End {
+    Root,
+    // A port of a node: 0 is the principal one, 1 and 2 the auxiliary ones.
+    Port(usize, u8),
+    // A side of a redex.
+    Cut(usize, bool),
+}
+
+#[derive(Default)]
+struct Machine {
+    labels: Vec<NodeLabel>,
+    table: Arc<LabelTable>,
+    wires: BTreeMap<End, End>,
+    pending: BTreeMap<DefaultKey, End>,
+    // The leaves of the root tree, counted from the left.
+    leaves: BTreeMap<End, usize>,
+    in_root: bool,
+}
+
+type Stacks = BTreeMap<NodeLabel, Vec<bool>>;
+
+// What a token that left the root with `inputs`, first choice first, came
+// back with, and the leaves of the root tree it left and entered it by.
+struct Run {
+    inputs: Stacks,
+    outputs: Stacks,
+    leaves: Option<(usize, usize)>,
+}
+
+impl Machine {
+    fn new(net: &Net) -> Self {
+        let mut net = net.clone();
+        net.resolve_vars();
+        let mut machine = Machine {
+            table: net.labels.clone(),
+            in_root: true,
+            ..Default::default()
+        };
+        machine.add(&net.root, End::Root);
+        machine.in_root = false;
+        for (i, (a, b)) in net.redexes.iter().enumerate() {
+            machine.add(a, End::Cut(i, true));
+            machine.add(b, End::Cut(i, false));
+        }
+        machine
+    }
+    fn link(&mut self, a: End, b: End) {
+        self.wires.insert(a, b);
+        self.wires.insert(b, a);
+    }
+    fn add(&mut self, tree: &Tree, at: End) {
+        match tree {
+            Tree::Binary { label, p1, p2 } => {
+                let n = self.labels.len();
+                self.labels.push(*label);
+                self.link(at, End::Port(n, 0));
+                self.add(p1, End::Port(n, 1));
+                self.add(p2, End::Port(n, 2));
+            }
+            Tree::Var { id } => {
+                if self.in_root {
+                    self.leaves.insert(at, self.leaves.len());
+                }
+                match self.pending.remove(id) {
+                    Some(other) => self.link(at, other),
+                    None => {
+                        self.pending.insert(*id, at);
+                    }
+                }
+            }
+        }
+    }
+    // Sends a token out of the root with the given inputs, first choice
+    // first. Returns what it comes back with, or the label whose stack ran
+    // out.
+    fn run(&self, inputs: Stacks, steps: &mut usize) -> Result<Result<Run, NodeLabel>, String> {
+        let mut stacks: Stacks = inputs
+            .iter()
+            .map(|(label, input)| (*label, input.iter().rev().copied().collect()))
+            .collect();
+        let mut from = End::Root;
+        let mut commuted = Stacks::new();
+        let (mut left, mut entered) = (None, None);
+        loop {
+            *steps += 1;
+            if *steps > MAX_STEPS {
+                return Err(
+                    "the token does not come back; the net may not have a normal form".into(),
+                );
+            }
+            let Some(&to) = self.wires.get(&from) else {
+                return Err("the token reached a dangling wire".into());
+            };
+            left = left.or(self.leaves.get(&from).copied());
+            entered = self.leaves.get(&to).copied().or(entered);
+            from = match to {
+                End::Root => {
+                    for (label, pushed) in commuted {
+                        stacks.entry(label).or_default().extend(pushed);
+                    }
+                    let leaves = left.zip(entered);
+                    return Ok(Ok(Run {
+                        inputs,
+                        outputs: stacks,
+                        leaves,
+                    }));
+                }
+                End::Cut(i, side) => {
+                    let nodes = (
+                        self.wires[&End::Cut(i, side)],
+                        self.wires[&End::Cut(i, !side)],
+                    );
+                    if let (End::Port(a, 0), End::Port(b, 0)) = nodes {
+                        if self.table.rule(self.labels[a], self.labels[b]).is_some() {
+                            return Err(
+                                "the token reached a pair of nodes with a custom rule".into()
+                            );
+                        }
+                    }
+                    End::Cut(i, !side)
+                }
+                End::Port(n, 0) => match stacks.entry(self.labels[n]).or_default().pop() {
+                    Some(first) => End::Port(n, if first { 1 } else { 2 }),
+                    None => return Ok(Err(self.labels[n])),
+                },
+                End::Port(n, port) => {
+                    let label = self.labels[n];
+                    let stacks = match self.table.annihilates(label) {
+                        true => &mut stacks,
+                        false => &mut commuted,
+                    };
+                    stacks.entry(label).or_default().push(port == 1);
+                    End::Port(n, 0)
+                }
+            };
+        }
+    }
+}
+
+// Runs tokens out of the root with every input they need.
+fn runs(machine: &Machine, steps: &mut usize) -> Result<Vec<Run>, String> {
+    let mut runs = vec![];
+    let mut pending = vec![Stacks::new()];
+    while let Some(inputs) = pending.pop() {
+        match machine.run(inputs.clone(), steps)? {
+            Ok(run) => runs.push(run),
+            Err(label) => {
+                for first in [true, false] {
+                    let mut inputs = inputs.clone();
+                    inputs.entry(label).or_default().push(first);
+                    pending.push(inputs);
+                }
+            }
+        }
+    }
+    Ok(runs)
+}
+
+fn path(run: &Run) -> Path {
+    let mut path = Path::new();
+    for (label, input) in &run.inputs {
+        path.entry(*label).or_default().0 = input.clone();
+    }
+    for (label, output) in &run.outputs {
+        path.entry(*label).or_default().1 = output.clone();
+    }
+    path.retain(|_, (input, output)| !input.is_empty() || !output.is_empty());
+    path
+}
+
+/// The paths of the net from the root back to it, in both directions, as
+/// followed by tokens.
+pub fn token_paths(net: &Net) -> Result<BTreeSet<Path>, String> {
+    let machine = Machine::new(net);
+    Ok(runs(&machine, &mut 0)?.iter().map(path).collect())
+}
+
+// Two tokens of a normal form that leave the root the same way, up to the
+// choices of transparent nodes, but come back differently, if any. Only
+// tokens that leave the root tree at a leaf left of the one they come back
+// by are compared, as in `Tree::is_coherent`.
+fn token_conflict(net: &Net) -> Result<Option<(Path, Path)>, String> {
+    let machine = Machine::new(net);
+    let mut steps = 0;
+    let runs: Vec<_> = runs(&machine, &mut steps)?
+        .into_iter()
+        .filter(|run| run.leaves.is_some_and(|(i, j)| i < j))
+        .collect();
+    let transparent = |label: &NodeLabel| net.labels.is_transparent(*label);
+    let observed = |run: &Run| {
+        let mut outputs = run.outputs.clone();
+        outputs.retain(|label, output| !transparent(label) && !output.is_empty());
+        outputs
+    };
+    for (n, a) in runs.iter().enumerate() {
+        'pairs: for b in &runs[n + 1..] {
+            // Both tokens can take the longer of their opaque inputs, and
+            // leave what they do not consume on their stacks.
+            let mut shared = Stacks::new();
+            for label in a.inputs.keys().chain(b.inputs.keys()) {
+                if transparent(label) {
+                    continue;
+                }
+                let x = a.inputs.get(label).map_or(&[][..], |x| x);
+                let y = b.inputs.get(label).map_or(&[][..], |x| x);
+                let (short, long) = if x.len() <= y.len() { (x, y) } else { (y, x) };
+                if !long.starts_with(short) || !net.labels.annihilates(*label) && x != y {
+                    continue 'pairs;
+                }
+                shared.insert(*label, long.to_vec());
+            }
+            let mut outputs = vec![];
+            for run in [a, b] {
+                let mut inputs = shared.clone();
+                inputs.extend(run.inputs.clone().into_iter().filter(|x| transparent(&x.0)));
+                match machine.run(inputs, &mut steps)? {
+                    Ok(run) => outputs.push(observed(&run)),
+                    Err(_) => return Err("a token did not retrace its path".into()),
+                }
+            }
+            if outputs[0] != outputs[1] {
+                return Ok(Some((path(a), path(b))));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn show(path: &Path) -> String {
+    let bits = |stack: &[bool]| match stack.len() {
+        0 => "-".to_string(),
+        _ => stack.iter().map(|x| if *x { 'l' } else { 'r' }).collect(),
+    };
+    let stacks = path
+        .iter()
+        .map(|(label, (input, output))| format!("{} {} {}", label.0, bits(input), bits(output)));
+    stacks.collect::<Vec<_>>().join(", ")
+}
+
+/// Runs tokens through the net and through its normal form, and checks that
+/// they follow the same paths, and that coherence of those paths agrees with
+/// both `Tree::is_coherent` and `Net::is_coherent` on the normal form. Fails
+/// if the net does not normalize within `MAX_INTERACTIONS`.
+pub fn oracle(net: &Net) -> Result<(), String> {
+    let before = token_paths(net)?;
+    let mut normal = net.clone();
+    if !normal.normal_within(MAX_INTERACTIONS) {
+        return Err(format!(
+            "does not normalize within {MAX_INTERACTIONS} interactions"
+        ));
+    }
+    normal.resolve_vars();
+    let after = token_paths(&normal)?;
+    if let Some(path) = before.symmetric_difference(&after).next() {
+        let (was, is) = match before.contains(path) {
+            true => ("before", "not after"),
+            false => ("after", "not before"),
+        };
+        return Err(format!(
+            "normalizing changes the paths of tokens: ({}) is taken {was} but {is}",
+            show(path)
+        ));
+    }
+    let conflict = token_conflict(&normal)?;
+    let tokens = conflict.is_none();
+    let tree = normal.root.is_coherent(&normal.labels);
+    let whole = normal.is_coherent();
+    if tokens != tree || Some(tokens) != whole {
+        let conflict = match conflict {
+            Some((a, b)) => format!(", since ({}) conflicts with ({})", show(&a), show(&b)),
+            None => String::new(),
+        };
+        return Err(format!(
+            "tokens say coherent? {tokens}, Tree::is_coherent {tree}, Net::is_coherent \
+             {whole:?}{conflict}"
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn token_oracle() {
+    let book = crate::parser::TreeParser::new(include_str!("../sample.itt"))
+        .parse_book()
+        .unwrap();
+    for (name, net) in &book.defs {
+        assert_eq!(oracle(net), Ok(()), "{name}");
+    }
+    for net in crate::enumerate::Enumerator::new(3) {
+        assert_eq!(oracle(&net), Ok(()), "{}", net.display());
+    }
+    // Both ways along the wire between two leaves of a normal form are
+    // followed, but only one is compared, as in `Tree::is_coherent`.
+    let net = crate::parser::TreeParser::new(
+        "(<<x0 x1> <x2 x1>> ((x3 x3) (x4 x5))) & ((x5 x4) (x6 x2)) = ([x0 x6] [x7 x7])",
+    )
+    .parse_net()
+    .unwrap();
+    assert_eq!(oracle(&net), Ok(()));
+    let net = crate::parser::TreeParser::new("(a a) & {1 x y} = (x y)")
+        .parse_net()
+        .unwrap();
+    assert!(oracle(&net).unwrap_err().contains("does not normalize"));
+}
@@ -0,0 +1,83 @@
+//! JSON reports of test results, written by hand to avoid a dependency.
+use std::fmt::Write;
+
+use crate::book::{Outcome, RootResult, TestResult};
+
+/// `s` as a JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn test(result: &TestResult) -> String {
+    let (verdict, expected) = match &result.outcome {
+        Outcome::Coherence {
+            coherent, expected, ..
+        } => (
+            match coherent {
+                Some(true) => "coherent",
+                Some(false) => "incoherent",
+                None => "unknown",
+            },
+            match expected {
+                Some(true) => string("coherent"),
+                Some(false) => string("incoherent"),
+                None => "null".to_string(),
+            },
+        ),
+        Outcome::NormalForm { expected, matches } => (
+            if *matches { "matches" } else { "differs" },
+            string(&expected.display()),
+        ),
+    };
+    format!(
+        "{{\"name\": {}, \"passed\": {}, \"verdict\": {}, \"expected\": {expected}, \
+         \"interactions\": {}, \"max_size\": {}, \"elapsed_ms\": {}, \"normal_form\": {}}}",
+        string(&result.name),
+        result.passed(),
+        string(verdict),
+        result.interactions,
+        result.max_size,
+        result.elapsed.as_secs_f64() * 1000.0,
+        string(&result.normal.display()),
+    )
+}
+
+/// A document with the results of the tests and of the root of a book.
+pub fn report(tests: &[TestResult], root: &RootResult) -> String {
+    let mut out = String::from("{\n  \"tests\": [");
+    for (i, result) in tests.iter().enumerate() {
+        out += if i == 0 { "\n    " } else { ",\n    " };
+        out += &test(result);
+    }
+    out += "\n  ],\n";
+    writeln!(
+        out,
+        "  \"root\": {{\"coherent\": {}, \"interactions\": {}, \"max_size\": {}, \
+         \"elapsed_ms\": {}, \"normal_form\": {}}}",
+        root.coherent.map_or("null".to_string(), |x| x.to_string()),
+        root.interactions,
+        root.max_size,
+        root.elapsed.as_secs_f64() * 1000.0,
+        string(&root.normal.display()),
+    )
+    .unwrap();
+    out += "}";
+    out
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(string("a \"b\"\n\\ λ\u{1}"), r#""a \"b\"\n\\ λ\u0001""#);
+}
@@ -0,0 +1,108 @@
+//! JUnit XML reports of test results, for CI viewers.
+use std::fmt::Write;
+
+use crate::book::{Outcome, TestResult};
+
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&apos;",
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// The message and details of a failed test.
+fn failure(result: &TestResult) -> (String, String) {
+    let verdict = |x: Option<bool>| match x {
+        Some(true) => "coherent",
+        Some(false) => "incoherent",
+        None => "unknown",
+    };
+    match &result.outcome {
+        Outcome::Coherence {
+            coherent, expected, ..
+        } => {
+            let message = format!(
+                "expected {}, found {}",
+                verdict(Some(expected.unwrap_or(true))),
+                verdict(*coherent)
+            );
+            let details = match result.normal.clone().incoherence() {
+                Ok(Some(explanation)) | Err(explanation) => {
+                    format!("{}\n{explanation}", result.normal.display())
+                }
+                Ok(None) => result.normal.display(),
+            };
+            (message, details)
+        }
+        Outcome::NormalForm { expected, .. } => (
+            "normal form differs".to_string(),
+            format!(
+                "expected: {}\nactual: {}",
+                expected.display(),
+                result.normal.display()
+            ),
+        ),
+    }
+}
+
+/// A test suite named `suite` with one test case per result.
+pub fn report(suite: &str, results: &[TestResult]) -> String {
+    let failures = results.iter().filter(|x| !x.passed()).count();
+    let time: f64 = results.iter().map(|x| x.elapsed.as_secs_f64()).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        out,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" time=\"{time}\">",
+        escape(suite),
+        results.len()
+    )
+    .unwrap();
+    for result in results {
+        write!(
+            out,
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+            escape(&result.name),
+            escape(suite),
+            result.elapsed.as_secs_f64()
+        )
+        .unwrap();
+        if result.passed() {
+            out += "/>\n";
+        } else {
+            let (message, details) = failure(result);
+            writeln!(
+                out,
+                ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                escape(&message),
+                escape(&details)
+            )
+            .unwrap();
+        }
+    }
+    out += "</testsuite>\n";
+    out
+}
+
+#[test]
+fn failing_tests() {
+    let book = crate::book::Book::parse(
+        "def Bool.true = ((a a) (b b))
+         def Unit = <(x x) (y y)>
+         check Bool.true : Unit
+         check not Bool.true : Unit
+         (a a)",
+    )
+    .unwrap();
+    let report = report("book & <tests>", &book.run_tests());
+    assert!(report.contains("name=\"book &amp; &lt;tests&gt;\" tests=\"2\" failures=\"1\""));
+    assert!(report.contains("<failure message=\"expected coherent, found incoherent\">"));
+    assert!(report.contains("conflicts with the one between leaves"));
+}
@@ -0,0 +1,131 @@
+//! Per-book semantics of node labels, declared in a book with
+//!
+//! ```text
+//! label EQL transparent annihilate "[" "]"
+//! ```
+//!
+//! where the delimiters are what nodes with the label are shown with, and
+//! can also be written with, and custom interaction rules between pairs of
+//! labels, written as the links that replace the two nodes, over their
+//! auxiliary ports `a1 a2` and `b1 b2`:
+//!
+//! ```text
+//! rule ANN ~ EQL => a1 = b1 & a2 = b2
+//! ```
+use std::{borrow::Cow, collections::BTreeMap};
+
+use slotmap::{DefaultKey as SlotKey, SlotMap};
+
+use crate::tree::{NodeLabel, Tree};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSemantics {
+    /// Whether coherence ignores how paths go through nodes with this label.
+    pub transparent: bool,
+    /// Whether two nodes with this label annihilate when they meet, instead
+    /// of commuting like nodes with different labels.
+    pub annihilate: bool,
+    pub open: String,
+    pub close: String,
+}
+
+impl LabelSemantics {
+    fn default_for(label: NodeLabel) -> Self {
+        let (open, close) = match label {
+            NodeLabel::CON => ("(".to_string(), ")"),
+            NodeLabel::EQL => ("[".to_string(), "]"),
+            NodeLabel::ANN => ("<".to_string(), ">"),
+            NodeLabel(n) => (format!("{{{n} "), "}"),
+        };
+        LabelSemantics {
+            transparent: label == NodeLabel::EQL,
+            annihilate: true,
+            open,
+            close: close.to_string(),
+        }
+    }
+}
+
+/// The net that replaces an active pair of nodes. Each of the four ports
+/// occurs once in `links`, and every other variable twice.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// The auxiliary ports `a1 a2` of the first node and `b1 b2` of the
+    /// second one.
+    pub ports: [SlotKey; 4],
+    pub vars: SlotMap<SlotKey, Option<Tree>>,
+    pub links: Vec<(Tree, Tree)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LabelTable {
+    labels: BTreeMap<NodeLabel, LabelSemantics>,
+    rules: BTreeMap<(NodeLabel, NodeLabel), Rule>,
+}
+
+impl LabelTable {
+    pub fn get(&self, label: NodeLabel) -> Cow<'_, LabelSemantics> {
+        match self.labels.get(&label) {
+            Some(semantics) => Cow::Borrowed(semantics),
+            None => Cow::Owned(LabelSemantics::default_for(label)),
+        }
+    }
+    pub fn set(&mut self, label: NodeLabel, semantics: LabelSemantics) {
+        self.labels.insert(label, semantics);
+    }
+    /// The declared labels, with the delimiters of their nodes.
+    pub fn delimiters(&self) -> impl Iterator<Item = (NodeLabel, &str, &str)> {
+        let labels = self.labels.iter();
+        labels.map(|(label, x)| (*label, x.open.as_str(), x.close.as_str()))
+    }
+    pub fn is_transparent(&self, label: NodeLabel) -> bool {
+        self.get(label).transparent
+    }
+    pub fn annihilates(&self, label: NodeLabel) -> bool {
+        self.get(label).annihilate
+    }
+    pub fn add_rule(&mut self, a: NodeLabel, b: NodeLabel, rule: Rule) {
+        self.rules.insert((a, b), rule);
+    }
+    /// The rule for nodes labeled `a` and `b`, and whether it was declared
+    /// with the two labels the other way around.
+    pub fn rule(&self, a: NodeLabel, b: NodeLabel) -> Option<(&Rule, bool)> {
+        match self.rules.get(&(a, b)) {
+            Some(rule) => Some((rule, false)),
+            None => self.rules.get(&(b, a)).map(|rule| (rule, true)),
+        }
+    }
+}
+
+#[test]
+fn declared_semantics() {
+    use crate::parser::TreeParser;
+    let src = "label 5 commute \"⟨\" \"⟩\"\n((a b) (c d)) & {5 a b} = {5 c d}";
+    let mut book = TreeParser::new(src).parse_book().unwrap();
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(book.root.display(), "((⟨x0 x1⟩ ⟨x2 x3⟩) (⟨x0 x2⟩ ⟨x1 x3⟩))");
+    // What is shown with declared delimiters can be read back.
+    for shown in [book.root.display(), book.root.pretty(10, true)] {
+        let src = format!("label 5 commute \"⟨\" \"⟩\"\n{shown}");
+        let mut parsed = TreeParser::new(&src).parse_book().unwrap().root;
+        parsed.normal(|_| ());
+        assert!(parsed.alpha_eq(&book.root), "{shown}");
+    }
+    assert!(TreeParser::new("label 5 \"\" \")\"\n(a a)")
+        .parse_book()
+        .is_err());
+
+    // The bad tests of the sample are only incoherent because paths may go
+    // either way through `EQL` nodes.
+    let sample = include_str!("../sample.itt");
+    for (src, coherent) in [
+        (sample.to_string(), false),
+        (format!("label EQL opaque\n{sample}"), true),
+    ] {
+        let book = TreeParser::new(&src).parse_book().unwrap();
+        let mut net = book.defs["badtest_1"].clone();
+        net.normal(|_| ());
+        assert_eq!(net.is_coherent(), Some(coherent));
+    }
+}
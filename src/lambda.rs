@@ -1,8 +1,16 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+};
+
 use slotmap::{DefaultKey, SlotMap};
 
-use crate::tree::{NodeLabel, Tree};
+use crate::{
+    labels::LabelTable,
+    tree::{Net, NetShow, NodeLabel, Tree},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Term {
     Binder {
         label: NodeLabel,
@@ -29,7 +37,73 @@ pub enum Term {
     },
 }
 
+/// Annotates `term` with the type `ty`, like applying
+/// `Check = ([b a] (<b c> [c a]))` to both, but without needing that
+/// definition.
+pub fn encode_check(
+    term: Tree,
+    ty: Tree,
+    vars: &mut SlotMap<DefaultKey, Option<Tree>>,
+    redex: &mut Vec<(Tree, Tree)>,
+) -> Tree {
+    let [a, b, c] = [(); 3].map(|_| vars.insert(None));
+    let node = |label, p1, p2| Tree::Binary {
+        label,
+        p1: Box::new(Tree::Var { id: p1 }),
+        p2: Box::new(Tree::Var { id: p2 }),
+    };
+    redex.push((term, node(NodeLabel::EQL, b, a)));
+    redex.push((ty, node(NodeLabel::ANN, b, c)));
+    node(NodeLabel::EQL, c, a)
+}
+
+impl Net {
+    /// The net of `check term : ty`.
+    pub fn check(term: Net, ty: Net) -> Net {
+        let mut net = Net {
+            root: Tree::Var {
+                id: DefaultKey::default(),
+            },
+            redexes: VecDeque::new(),
+            vars: SlotMap::new(),
+            names: BTreeMap::new(),
+            holes: BTreeMap::new(),
+            labels: term.labels.clone(),
+        };
+        let term = net.inject(term);
+        let ty = net.inject(ty);
+        let mut redexes = vec![];
+        net.root = encode_check(term, ty, &mut net.vars, &mut redexes);
+        net.redexes.extend(redexes);
+        net
+    }
+}
+
 impl Term {
+    /// Replaces each variable in `subst` by its image. Images are not
+    /// substituted again.
+    pub fn substitute(&mut self, subst: &BTreeMap<DefaultKey, Term>) {
+        match self {
+            Term::Binder {
+                pat: a, body: b, ..
+            }
+            | Term::Apply { fun: a, arg: b, .. }
+            | Term::Sup { fst: a, snd: b, .. } => {
+                a.substitute(subst);
+                b.substitute(subst);
+            }
+            Term::Let { pat, value, next } => {
+                pat.substitute(subst);
+                value.substitute(subst);
+                next.substitute(subst);
+            }
+            Term::Var { id } => {
+                if let Some(image) = subst.get(id) {
+                    *self = image.clone();
+                }
+            }
+        }
+    }
     pub fn encode(
         &self,
         vars: &mut SlotMap<DefaultKey, Option<Tree>>,
@@ -53,11 +127,11 @@ impl Term {
                     (Tree::Binary {
                         label: *label,
                         p1: Box::new(arg),
-                        p2: Box::new(Tree::Var { id: id }),
+                        p2: Box::new(Tree::Var { id }),
                     }),
                     fun,
                 ));
-                Tree::Var { id: id }
+                Tree::Var { id }
             }
             Term::Sup { label, fst, snd } => {
                 let fst = fst.encode(vars, redex);
@@ -79,3 +153,137 @@ impl Term {
         }
     }
 }
+
+impl Term {
+    pub fn display(
+        &self,
+        vars: &SlotMap<DefaultKey, Option<Tree>>,
+        names: &BTreeMap<DefaultKey, String>,
+    ) -> String {
+        let mut s = String::new();
+        let labels = LabelTable::default();
+        NetShow::new(|key| vars.get(key).and_then(|x| x.as_ref()), names, &labels)
+            .show_term(&mut s, self)
+            .unwrap();
+        s
+    }
+}
+
+impl<'a, F: Fn(DefaultKey) -> Option<&'a Tree>> NetShow<'a, F> {
+    fn show_label_prefix(&mut self, f: &mut impl Write, label: u64) -> std::fmt::Result {
+        if label != 0 {
+            write!(f, "#{label} ")?;
+        }
+        Ok(())
+    }
+    /// Writes a term in the syntax accepted by `TreeParser::parse_term`.
+    /// Variables bound to a tree are written as `tree <tree>`.
+    pub fn show_term(&mut self, f: &mut impl Write, term: &Term) -> std::fmt::Result {
+        match term {
+            Term::Binder { label, pat, body } => {
+                match *label {
+                    NodeLabel::ANN => f.write_str("θ")?,
+                    NodeLabel(n) if n % 2 == 0 => {
+                        self.show_label_prefix(f, n / 2)?;
+                        f.write_str("λ")?;
+                    }
+                    _ => return Err(std::fmt::Error),
+                }
+                self.show_term(f, pat)?;
+                f.write_str(" ")?;
+                self.show_term(f, body)?;
+            }
+            Term::Apply {
+                label: NodeLabel::ANN,
+                ..
+            }
+            | Term::Sup {
+                label: NodeLabel::EQL,
+                ..
+            } => {
+                // <a : b == c> nests to the left.
+                let mut chain = vec![];
+                let mut head = term;
+                loop {
+                    match head {
+                        Term::Apply {
+                            label: NodeLabel::ANN,
+                            fun,
+                            arg,
+                        } => {
+                            chain.push((":", &**fun));
+                            head = arg;
+                        }
+                        Term::Sup {
+                            label: NodeLabel::EQL,
+                            fst,
+                            snd,
+                        } => {
+                            chain.push((" ==", &**snd));
+                            head = fst;
+                        }
+                        _ => break,
+                    }
+                }
+                f.write_str("<")?;
+                self.show_term(f, head)?;
+                for (op, term) in chain.into_iter().rev() {
+                    f.write_str(op)?;
+                    f.write_str(" ")?;
+                    self.show_term(f, term)?;
+                }
+                f.write_str(">")?;
+            }
+            Term::Apply { label, .. } | Term::Sup { label, .. } => {
+                // `#l (a b)` parses to a `Sup` for any label other than `CON`.
+                let (open, close, is_apply) = match term {
+                    Term::Apply { .. } if label.0 % 2 == 0 => ("(", ")", true),
+                    Term::Sup { .. } if label.0 % 2 == 0 => ("(", ")", false),
+                    Term::Sup { .. } => ("{", "}", false),
+                    _ => return Err(std::fmt::Error),
+                };
+                // (f a b) and {a b c} nest to the left.
+                let mut args = vec![];
+                let mut head = term;
+                loop {
+                    match head {
+                        Term::Apply { label: l, fun, arg } if is_apply && l == label => {
+                            args.push(&**arg);
+                            head = fun;
+                        }
+                        Term::Sup { label: l, fst, snd } if !is_apply && l == label => {
+                            args.push(&**snd);
+                            head = fst;
+                        }
+                        _ => break,
+                    }
+                }
+                self.show_label_prefix(f, label.0 / 2)?;
+                f.write_str(open)?;
+                self.show_term(f, head)?;
+                for arg in args.into_iter().rev() {
+                    f.write_str(" ")?;
+                    self.show_term(f, arg)?;
+                }
+                f.write_str(close)?;
+            }
+            Term::Let { pat, value, next } => {
+                f.write_str("let ")?;
+                self.show_term(f, pat)?;
+                f.write_str(" = ")?;
+                self.show_term(f, value)?;
+                f.write_str("; ")?;
+                self.show_term(f, next)?;
+            }
+            Term::Var { id } => {
+                if let Some(tree) = (self.vars)(*id) {
+                    f.write_str("tree ")?;
+                    self.show_tree(f, tree)?;
+                } else {
+                    f.write_str(&self.get_or_new(*id))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+#![feature(box_patterns, test)]
+//! Interaction nets with labels, a lambda-like term syntax that encodes to
+//! them, and coherence checking of their normal forms.
+
+#[cfg(test)]
+extern crate test;
+
+pub mod book;
+pub mod canonical;
+pub mod certificate;
+pub mod coherence;
+pub mod confluence;
+pub mod enumerate;
+pub mod equiv;
+pub mod fill;
+pub mod formatter;
+pub mod goi;
+pub mod json;
+pub mod junit;
+pub mod labels;
+pub mod lambda;
+pub mod parallel;
+pub mod parser;
+pub mod pretty;
+pub mod run;
+pub mod tree;
+
+pub use book::{Book, Error, Expect, Outcome, RootResult, Test, TestResult};
@@ -1,33 +1,375 @@
-#![feature(box_patterns, debug_closure_helpers)]
+use fixpoint_itt::{
+    certificate, confluence, equiv,
+    equiv::Verdict,
+    fill, formatter, goi, json, junit,
+    run::{Reducer, Strategy},
+    tree::Net,
+    Book, Outcome, TestResult,
+};
 
-pub mod coherence;
-pub mod lambda;
-pub mod parser;
-pub mod run;
-pub mod tree;
+const USAGE: &str =
+    "usage: fixpoint-itt <file> [--certificate <out>] [--format text|json] [--junit <out>] [-j <jobs>]
+                    [--reducer sequential|parallel|lazy] [--strategy lifo|fifo|random[:<seed>]|root-first]
+                    [--width <columns>] [--wires inline|named]
+       fixpoint-itt equiv <file> <A> <B>
+       fixpoint-itt verify <file> <certificate>
+       fixpoint-itt oracle <file>
+       fixpoint-itt confluence <file>
+       fixpoint-itt fmt <file>
+       fixpoint-itt fill <file> [<def>]";
 
 fn main() {
-    let code = std::fs::read_to_string(std::env::args().skip(1).next().unwrap()).unwrap();
-    let mut book = match parser::TreeParser::new(&code).parse_book() {
-        Ok(o) => o,
-        Err(e) => todo!("{}", e),
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["equiv", file, a, b] => equiv(&load(file), a, b),
+        ["verify", file, certificate] => verify(&load(file), certificate),
+        ["oracle", file] => oracle(&load(file)),
+        ["confluence", file] => confluence(&load(file)),
+        ["fmt", file] => format(file),
+        ["fill", file] => fill(&load(file), None),
+        ["fill", file, name] => fill(&load(file), Some(name)),
+        [file, ref options @ ..] => match Options::parse(options) {
+            Ok(options) => run(load(file), file, options),
+            Err(e) => eprintln!("{e}\n{USAGE}"),
+        },
+        [] => eprintln!("{USAGE}"),
+    }
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+struct Options<'a> {
+    certificate: Option<&'a str>,
+    format: Format,
+    junit: Option<&'a str>,
+    jobs: usize,
+    reducer: Reducer,
+    width: Option<usize>,
+    wires: bool,
+}
+
+fn parse_strategy(strategy: &str) -> Result<Strategy, String> {
+    match strategy {
+        "lifo" => Ok(Strategy::Lifo),
+        "fifo" => Ok(Strategy::Fifo),
+        "random" => Ok(Strategy::Random(0)),
+        "root-first" => Ok(Strategy::RootFirst),
+        _ => match strategy.strip_prefix("random:").map(str::parse) {
+            Some(Ok(seed)) => Ok(Strategy::Random(seed)),
+            _ => Err(format!("unknown strategy `{strategy}`")),
+        },
+    }
+}
+
+impl<'a> Options<'a> {
+    fn parse(mut args: &[&'a str]) -> Result<Self, String> {
+        let mut options = Options {
+            certificate: None,
+            format: Format::Text,
+            junit: None,
+            jobs: 1,
+            reducer: Reducer::default(),
+            width: None,
+            wires: false,
+        };
+        let mut strategy = None;
+        while let [option, value, rest @ ..] = args {
+            match (*option, *value) {
+                ("--certificate", path) => options.certificate = Some(path),
+                ("--format", "text") => options.format = Format::Text,
+                ("--format", "json") => options.format = Format::Json,
+                ("--junit", path) => options.junit = Some(path),
+                ("--reducer", "sequential") => options.reducer = Reducer::default(),
+                ("--reducer", "parallel") => {
+                    let threads = std::thread::available_parallelism().map_or(1, |x| x.get());
+                    options.reducer = Reducer::Parallel(threads)
+                }
+                ("--strategy", value) => strategy = Some(parse_strategy(value)?),
+                ("--reducer", "lazy") => options.reducer = Reducer::Lazy,
+                ("--width", width) => {
+                    let width = width
+                        .parse()
+                        .map_err(|_| format!("`{width}` is not a width"))?;
+                    options.width = Some(width)
+                }
+                ("--wires", "inline") => options.wires = false,
+                ("--wires", "named") => options.wires = true,
+                ("-j", jobs) => {
+                    options.jobs = jobs
+                        .parse()
+                        .map_err(|_| format!("`{jobs}` is not a number of jobs"))?
+                }
+                _ => return Err(format!("unknown option `{option} {value}`")),
+            }
+            args = rest;
+        }
+        match (options.reducer, strategy) {
+            (Reducer::Sequential(_), Some(strategy)) => {
+                options.reducer = Reducer::Sequential(strategy)
+            }
+            (_, Some(_)) => return Err("`--strategy` needs the sequential reducer".to_string()),
+            (_, None) => (),
+        }
+        match args {
+            [] => Ok(options),
+            [option, ..] => Err(format!("option `{option}` needs a value")),
+        }
+    }
+}
+
+impl Options<'_> {
+    fn show(&self, net: &Net) -> String {
+        match self.width {
+            None if !self.wires => net.display(),
+            width => net.pretty(width.unwrap_or(formatter::WIDTH), self.wires),
+        }
+    }
+}
+
+fn load(file: &str) -> Book {
+    match Book::load(file) {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("{file}: {e}");
+            std::process::exit(1)
+        }
+    }
+}
+
+fn equiv(book: &Book, a: &str, b: &str) {
+    match equiv::equiv(book, a, b, equiv::MAX_DEPTH) {
+        Ok(Verdict::Equivalent) => println!("{a} and {b} are equivalent"),
+        Ok(Verdict::NoDifference { tried }) => println!(
+            "{a} and {b} are equivalent on all {tried} terms tried, up to depth {}",
+            equiv::MAX_DEPTH
+        ),
+        Ok(Verdict::Distinguished { witness, first }) => {
+            let (yes, no) = if first { (a, b) } else { (b, a) };
+            println!("{a} and {b} are not equivalent: {witness} checks against {yes} but not {no}")
+        }
+        Err(e) => eprintln!("{e}"),
+    }
+}
+
+fn verify(book: &Book, certificate: &str) {
+    let certificate = match std::fs::read_to_string(certificate) {
+        Ok(certificate) => certificate,
+        Err(e) => return eprintln!("{certificate}: {e}"),
+    };
+    for (name, result) in certificate::verify_book(book, &certificate) {
+        match result {
+            Ok(()) => eprintln!("net {name}: ✔️ verified"),
+            Err(e) => eprintln!("net {name}: ✖️ {e}"),
+        }
+    }
+}
+
+// Fills the holes of the tests, definitions and root of the book, or of the
+// one named `only`, with `root` standing for the root.
+fn fill(book: &Book, only: Option<&str>) {
+    let tests = book.tests.iter().map(|x| (x.name.as_str(), &x.net));
+    // Tests written as `def test` are definitions too.
+    let defs = book.defs.iter().map(|(name, net)| (name.as_str(), net));
+    let defs = defs.filter(|(name, _)| book.tests.iter().all(|x| x.name != *name));
+    let nets: Vec<_> = tests
+        .chain(defs)
+        .chain([("root", &book.root)])
+        .filter(|(name, _)| only.is_none_or(|x| x == *name))
+        .collect();
+    if let (Some(name), []) = (only, &nets[..]) {
+        return eprintln!("no definition `{name}`");
+    }
+    for (name, net) in nets {
+        if net.holes.is_empty() {
+            if only.is_some() {
+                eprintln!("net {name}: no holes");
+            }
+            continue;
+        }
+        let solutions = match fill::fill(book, net, fill::MAX_DEPTH) {
+            Ok(solutions) => solutions,
+            Err(e) => {
+                eprintln!("net {name}: {e}");
+                continue;
+            }
+        };
+        eprintln!("net {name}: {} solutions", solutions.len());
+        for solution in solutions {
+            let fills: Vec<_> = solution
+                .fills
+                .iter()
+                .map(|(hole, fill)| format!("?{hole} = {fill}"))
+                .collect();
+            eprintln!("  {} (size {})", fills.join(", "), solution.size);
+        }
+    }
+}
+
+fn format(file: &str) {
+    let formatted = std::fs::read_to_string(file)
+        .map_err(|e| e.to_string())
+        .and_then(|source| formatter::format(&source));
+    match formatted {
+        Ok(formatted) => write(file, formatted),
+        Err(e) => eprintln!("{file}: {e}"),
+    }
+}
+
+fn oracle(book: &Book) {
+    let defs = book.defs.iter().map(|(name, net)| (name.as_str(), net));
+    let nets = defs.chain([("root", &book.root)]);
+    let mut agree = 0;
+    for (name, net) in nets {
+        match goi::oracle(net) {
+            Ok(()) => agree += 1,
+            Err(e) => eprintln!("net {name}: ✖️ {e}"),
+        }
+    }
+    eprintln!("{agree} of {} nets agree", book.defs.len() + 1);
+}
+
+fn confluence(book: &Book) {
+    let defs = book.defs.iter().map(|(name, net)| (name.as_str(), net));
+    let nets = defs.chain([("root", &book.root)]);
+    let mut confluent = 0;
+    for (name, net) in nets {
+        let report = match confluence::confluence(net, confluence::SEEDS) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("net {name}: ✖️ {e}");
+                continue;
+            }
+        };
+        if report.confluent() {
+            confluent += 1;
+            eprintln!(
+                "net {name}: ✔️ same normal form in {} orders",
+                report.orders
+            );
+            continue;
+        }
+        eprintln!("net {name}: ✖️ not confluent in {} orders", report.orders);
+        if let Some((strategy, lifo, other)) = report.divergence {
+            eprintln!("  lifo: {}", lifo.display());
+            eprintln!("  {strategy}: {}", other.display());
+        }
+        if !report.coherence_agrees {
+            eprintln!("  coherence verdicts differ");
+        }
+    }
+    eprintln!("{confluent} of {} nets are confluent", book.defs.len() + 1);
+}
+
+fn run(mut book: Book, file: &str, options: Options) {
+    // Tests are about normal forms, so only the root is reduced lazily.
+    book.reducer = match options.reducer {
+        Reducer::Lazy => Reducer::default(),
+        reducer => reducer,
     };
-    for test_name in book.tests {
-        let mut test = book.defs.get(&test_name).unwrap().clone();
-        test.validate();
-        test.normal(|_| ());
-        eprintln!(
-            "test {test_name}: {}",
-            if test.is_coherent() {
-                "✔️ coherent"
+    let mut results = book.run_tests_parallel(options.jobs);
+    book.reducer = options.reducer;
+    if let Some(path) = options.junit {
+        write(path, junit::report(file, &results));
+    }
+    match options.format {
+        Format::Text => {
+            for result in &mut results {
+                show_result(result, &options);
+            }
+            match book.reducer {
+                Reducer::Sequential(strategy) => book
+                    .root
+                    .normal_with(strategy, |x| println!("{}", x.display())),
+                reducer => book.root.reduce(reducer),
+            };
+            book.root.resolve_vars();
+            println!("{}", options.show(&book.root));
+            println!("Is coherent? {}", verdict(book.root.is_coherent()));
+        }
+        Format::Json => {
+            let root = book.run_root();
+            println!("{}", json::report(&results, &root));
+            book.root = root.normal;
+        }
+    }
+    if let Some(path) = options.certificate {
+        let mut certificates = String::new();
+        let coherence = results
+            .iter_mut()
+            .filter(|x| matches!(x.outcome, Outcome::Coherence { .. }))
+            .map(|x| (x.name.as_str(), &mut x.normal));
+        for (name, net) in coherence.chain([("root", &mut book.root)]) {
+            match net.certificate() {
+                Ok(Some(c)) => certificates += &format!("net {name}\n{c}"),
+                Ok(None) => (),
+                Err(e) => eprintln!("net {name}: no certificate: {e}"),
+            }
+        }
+        write(path, certificates);
+    }
+}
+
+fn write(path: &str, contents: String) {
+    if let Err(e) = std::fs::write(path, contents) {
+        eprintln!("{path}: {e}");
+    }
+}
+
+// Whether a net is coherent, when it is known.
+fn verdict(coherent: Option<bool>) -> String {
+    coherent.map_or("unknown".to_string(), |x| x.to_string())
+}
+
+fn show_result(result: &mut TestResult, options: &Options) {
+    let name = &result.name;
+    let net = &mut result.normal;
+    match &mut result.outcome {
+        Outcome::Coherence {
+            coherent,
+            expected,
+            before,
+            root_only,
+        } => {
+            let mark = if Some(expected.unwrap_or(true)) == *coherent {
+                "✔️"
             } else {
-                "✖️ incoherent"
+                "✖️"
+            };
+            let word = match coherent {
+                Some(true) => "coherent",
+                Some(false) => "incoherent",
+                None => "coherence unknown",
+            };
+            eprintln!("test {name}: {mark} {word}");
+            if !net.holes.is_empty() {
+                eprintln!("  residual: {}", options.show(net));
+            }
+            if before != coherent {
+                eprintln!(
+                    "  note: before normalizing, the net is coherent? {}",
+                    verdict(*before)
+                );
+            }
+            if before.is_some_and(|x| x != *root_only) {
+                eprintln!("  note: ignoring its redexes, the net is coherent? {root_only}");
             }
-        )
+        }
+        Outcome::NormalForm { matches: true, .. } => {
+            eprintln!("test {name}: ✔️ normal form");
+        }
+        Outcome::NormalForm {
+            expected,
+            matches: false,
+        } => {
+            net.canonicalize();
+            expected.canonicalize();
+            eprintln!("test {name}: ✖️ normal form differs");
+            eprintln!("  - {}", options.show(expected));
+            eprintln!("  + {}", options.show(net));
+        }
     }
-    book.root.validate();
-    book.root.normal(|x| println!("{}", x.display()));
-    book.root.root.resolve_vars(&mut book.root.vars);
-    println!("{}", book.root.display());
-    println!("Is coherent? {}", book.root.root.is_coherent());
 }
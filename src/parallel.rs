@@ -0,0 +1,211 @@
+//! Reduces the redexes of a net on several threads. Wires live in a store
+//! split in shards, each behind its own lock, and redexes in a queue shared by
+//! all threads, which sleep while it is empty. Connecting to a wire only locks
+//! its shard, so two threads reaching the two ends of a wire agree on which of
+//! them continues.
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicIsize, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
+
+use slotmap::{DefaultKey as SlotKey, Key, KeyData};
+
+use crate::{
+    labels::LabelTable,
+    run::{interact, ReductionStats, Wires},
+    tree::{Net, Tree},
+};
+
+const SHARDS: usize = 64;
+
+struct Shared {
+    labels: Arc<LabelTable>,
+    shards: Vec<Mutex<BTreeMap<SlotKey, Option<Tree>>>>,
+    next_wire: AtomicU64,
+    queue: Mutex<Queue>,
+    // Notified when redexes are queued, or when there are none left.
+    ready: Condvar,
+    interactions: AtomicUsize,
+    size: AtomicIsize,
+    max_size: AtomicUsize,
+}
+
+struct Queue {
+    redexes: Vec<(Tree, Tree)>,
+    // Redexes queued or being reduced.
+    pending: usize,
+}
+
+impl Shared {
+    fn shard(&self, id: SlotKey) -> &Mutex<BTreeMap<SlotKey, Option<Tree>>> {
+        let index = id.data().as_ffi() & 0xffff_ffff;
+        &self.shards[index as usize % SHARDS]
+    }
+}
+
+// A thread's view of the shared store, which collects the redexes of one
+// interaction before queueing them.
+struct Worker<'a> {
+    shared: &'a Shared,
+    redexes: Vec<(Tree, Tree)>,
+}
+
+impl Wires for Worker<'_> {
+    fn labels(&self) -> Arc<LabelTable> {
+        self.shared.labels.clone()
+    }
+    fn wire(&mut self) -> SlotKey {
+        // Keys with version 1 and indices above those of the original net's,
+        // which are only unique among themselves, not valid in any `SlotMap`.
+        let index = self.shared.next_wire.fetch_add(1, Ordering::Relaxed);
+        let id = KeyData::from_ffi(1 << 32 | index).into();
+        self.shared.shard(id).lock().unwrap().insert(id, None);
+        id
+    }
+    fn connect(&mut self, id: SlotKey, tree: Tree) -> Option<(Tree, Tree)> {
+        let mut shard = self.shared.shard(id).lock().unwrap();
+        let entry = shard.get_mut(&id).unwrap();
+        if entry.is_some() {
+            Some((shard.remove(&id).unwrap().unwrap(), tree))
+        } else {
+            *entry = Some(tree);
+            None
+        }
+    }
+    fn bind(&mut self, id: SlotKey, tree: Tree) {
+        self.shared.shard(id).lock().unwrap().insert(id, Some(tree));
+    }
+    fn link(&mut self, a: Tree, b: Tree) {
+        self.redexes.push((a, b));
+    }
+}
+
+impl Worker<'_> {
+    fn run(&mut self) {
+        let shared = self.shared;
+        let mut queue = shared.queue.lock().unwrap();
+        loop {
+            let Some((a, b)) = queue.redexes.pop() else {
+                if queue.pending == 0 {
+                    return;
+                }
+                queue = shared.ready.wait(queue).unwrap();
+                continue;
+            };
+            drop(queue);
+            let delta = interact(self, a, b);
+            shared.interactions.fetch_add(1, Ordering::Relaxed);
+            let size = shared.size.fetch_add(delta, Ordering::Relaxed) + delta;
+            shared.max_size.fetch_max(size as usize, Ordering::Relaxed);
+            queue = shared.queue.lock().unwrap();
+            let new = self.redexes.len();
+            queue.redexes.append(&mut self.redexes);
+            queue.pending = queue.pending + new - 1;
+            // This thread takes one of the new redexes itself.
+            if queue.pending == 0 {
+                shared.ready.notify_all();
+            }
+            for _ in 1..new {
+                shared.ready.notify_one();
+            }
+        }
+    }
+}
+
+impl Net {
+    /// Reduces the net to normal form on `threads` threads. The normal form is
+    /// the same as `normal`'s, up to the naming of wires. The largest size is
+    /// the largest seen by the threads, and varies from run to run.
+    pub fn normal_parallel(&mut self, threads: usize) -> ReductionStats {
+        let next_wire = self.vars.keys().map(|k| k.data().as_ffi() & 0xffff_ffff);
+        let size = self.size();
+        let shared = Shared {
+            labels: self.labels.clone(),
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            next_wire: AtomicU64::new(next_wire.max().map_or(0, |x| x + 1)),
+            queue: Mutex::new(Queue {
+                pending: self.redexes.len(),
+                redexes: core::mem::take(&mut self.redexes).into(),
+            }),
+            ready: Condvar::new(),
+            interactions: AtomicUsize::new(0),
+            size: AtomicIsize::new(size as isize),
+            max_size: AtomicUsize::new(size),
+        };
+        for (id, value) in core::mem::take(&mut self.vars) {
+            shared.shard(id).lock().unwrap().insert(id, value);
+        }
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                scope.spawn(|| {
+                    Worker {
+                        shared: &shared,
+                        redexes: vec![],
+                    }
+                    .run()
+                });
+            }
+        });
+
+        // Move the remaining wires back into a `SlotMap`, with new keys.
+        let mut remap = BTreeMap::new();
+        let mut values = vec![];
+        for shard in shared.shards {
+            for (id, value) in shard.into_inner().unwrap() {
+                remap.insert(id, self.vars.insert(None));
+                values.extend(value.map(|value| (remap[&id], value)));
+            }
+        }
+        for (id, value) in values {
+            self.vars[id] = Some(value);
+        }
+        self.recurse_mut(&mut |tree| tree.map_var_id(|id| remap.get(&id).cloned()));
+        self.names = core::mem::take(&mut self.names)
+            .into_iter()
+            .filter_map(|(id, name)| Some((*remap.get(&id)?, name)))
+            .collect();
+        ReductionStats {
+            interactions: shared.interactions.into_inner(),
+            max_size: shared.max_size.into_inner(),
+        }
+    }
+}
+
+#[test]
+fn same_normal_form() {
+    let book = crate::book::Book::parse(include_str!("../sample.itt")).unwrap();
+    for (name, net) in &book.defs {
+        let mut sequential = net.clone();
+        let mut parallel = net.clone();
+        let interactions = sequential.normal(|_| ());
+        let stats = parallel.normal_parallel(4);
+        assert_eq!(stats.interactions, interactions, "{name}");
+        assert!(sequential.alpha_eq(&parallel), "{name}");
+    }
+}
+
+#[test]
+fn independent_redexes() {
+    // Many copies of a computation of about fifty interactions each, which
+    // the threads reduce side by side.
+    fn copies(depth: usize) -> String {
+        match depth {
+            0 => "term (Two Two Two Two)".to_string(),
+            _ => format!("({} {})", copies(depth - 1), copies(depth - 1)),
+        }
+    }
+    let src = format!("def Two = term λf λx (f (f x))\n{}", copies(6));
+    let book = crate::book::Book::parse(&src).unwrap();
+    assert_eq!(book.root.redexes.len(), 64 * 11);
+    for threads in [2, 8] {
+        let mut sequential = book.root.clone();
+        let mut parallel = book.root.clone();
+        let interactions = sequential.normal(|_| ());
+        let stats = parallel.normal_parallel(threads);
+        assert_eq!(stats.interactions, interactions);
+        assert!(sequential.alpha_eq(&parallel));
+    }
+}
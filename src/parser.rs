@@ -1,10 +1,12 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, sync::Arc};
 
 use slotmap::{DefaultKey, SlotMap};
 use TSPL::Parser;
 
 use crate::{
-    lambda::Term,
+    book::{Book, Expect, Test},
+    labels::{LabelTable, Rule},
+    lambda::{encode_check, Term},
     tree::{Net, NodeLabel, Tree},
 };
 
@@ -22,15 +24,29 @@ pub struct TreeParser<'i> {
     input: &'i str,
     index: usize,
     scope: BTreeMap<String, DefaultKey>,
+    // Source names of the variables of the net being parsed.
     back_scope: BTreeMap<DefaultKey, String>,
     redexes: Vec<(Tree, Tree)>,
     vars: SlotMap<DefaultKey, Option<Tree>>,
     defs: BTreeMap<String, Net>,
+    // Occurrences of each variable name in the term being parsed that are not
+    // bound yet, and whether each one is in pattern position.
+    term_vars: BTreeMap<String, Vec<(DefaultKey, bool)>>,
+    in_pattern: bool,
+    // What the occurrences of bound variables are replaced with.
+    term_subst: BTreeMap<DefaultKey, Term>,
+    next_dup_label: u64,
+    labels: LabelTable,
+    holes: BTreeMap<String, Tree>,
 }
 
+/// Labels of duplicators inserted for non-linear term variables start here,
+/// well above anything written by hand.
+const AUTO_DUP_LABEL: u64 = 1 << 32;
+
 impl<'i> Parser<'i> for TreeParser<'i> {
     fn input(&mut self) -> &'i str {
-        &self.input
+        self.input
     }
 
     fn index(&mut self) -> &mut usize {
@@ -39,6 +55,13 @@ impl<'i> Parser<'i> for TreeParser<'i> {
 }
 
 impl<'i> TreeParser<'i> {
+    /// A parser that can refer to the given definitions.
+    pub fn with_defs(input: &'i str, defs: BTreeMap<String, Net>) -> Self {
+        Self {
+            defs,
+            ..Self::new(input)
+        }
+    }
     pub fn new(input: &'i str) -> Self {
         Self {
             input,
@@ -47,7 +70,13 @@ impl<'i> TreeParser<'i> {
             vars: Default::default(),
             defs: Default::default(),
             back_scope: BTreeMap::new(),
-            redexes: vec![],
+            redexes: Default::default(),
+            term_vars: Default::default(),
+            in_pattern: false,
+            term_subst: Default::default(),
+            next_dup_label: AUTO_DUP_LABEL,
+            labels: Default::default(),
+            holes: Default::default(),
         }
     }
 }
@@ -57,11 +86,9 @@ impl<'i> TreeParser<'i> {
         while let Some(c) = self.peek_one() {
             if c.is_ascii_whitespace() {
                 self.advance_one();
-            } else if c == '#' {
-                if self.peek_many(2) == Some("##") {
-                    while self.peek_one().is_some_and(|x| x != '\n') {
-                        self.advance_one();
-                    }
+            } else if self.peek_many(2) == Some("##") {
+                while self.peek_one().is_some_and(|x| x != '\n') {
+                    self.advance_one();
                 }
             } else {
                 break;
@@ -73,41 +100,169 @@ impl<'i> TreeParser<'i> {
             e
         } else {
             let v = self.vars.insert(None);
-            self.back_scope.insert(v, format!("Var({name})"));
+            self.back_scope.insert(v, name.clone());
             self.scope.insert(name, v);
             v
         }
     }
-    pub fn inject(&mut self, mut net: Net) -> Tree {
-        let mut remap = BTreeMap::new();
-        for (k, v) in core::mem::take(&mut net.vars) {
-            let id = self.vars.insert(v);
-            remap.insert(k, id);
-            assert!(self.back_scope.insert(id, format!("Remapped")).is_none());
-        }
-        let mut remap_fun = |key: DefaultKey| remap.get(&key).cloned();
-        for remap_to in remap.values() {
-            if let Some(Some(v)) = self.vars.get_mut(*remap_to) {
-                v.recurse_mut(&mut |tree: &mut Tree| {
-                    tree.map_var_id(&mut remap_fun);
-                });
-            }
+    pub fn inject(&mut self, net: Net) -> Tree {
+        crate::tree::inject(
+            net,
+            &mut self.vars,
+            &mut self.back_scope,
+            &mut self.redexes,
+            &mut self.holes,
+        )
+    }
+    // ?name
+    fn parse_hole(&mut self) -> Result<DefaultKey, String> {
+        let start = self.index;
+        self.consume("?")?;
+        let name = self.parse_name()?;
+        let id = self.vars.insert(None);
+        self.insert_hole(start, name, Tree::Var { id })?;
+        Ok(id)
+    }
+    fn insert_hole(&mut self, start: usize, name: String, tree: Tree) -> Result<(), String> {
+        match self.holes.insert(name.clone(), tree) {
+            Some(_) => self.expected_at(start, &format!("hole `?{name}` to occur once")),
+            None => Ok(()),
         }
-        net.recurse_mut(&mut |tree: &mut Tree| {
-            tree.map_var_id(&mut remap_fun);
-        });
-        self.redexes.extend(net.redexes);
-        net.root
+    }
+    // Reports what was expected at `index`, for errors found after parsing
+    // past it.
+    fn expected_at<T>(&mut self, index: usize, exp: &str) -> Result<T, String> {
+        self.index = index;
+        self.expected(exp)
     }
     pub fn to_var(&mut self, tree: Tree) -> DefaultKey {
-        let id = self.vars.insert(Some(tree));
-        assert!(self
-            .back_scope
-            .insert(id, format!("Created from tree"))
-            .is_none());
+        self.vars.insert(Some(tree))
+    }
+    fn fresh_dup_label(&mut self) -> NodeLabel {
+        let label = NodeLabel(self.next_dup_label * 2 + 1);
+        self.next_dup_label += 1;
+        label
+    }
+    fn term_var(&mut self, name: String) -> DefaultKey {
+        let id = self.vars.insert(None);
+        self.back_scope.insert(id, name.clone());
+        self.term_vars
+            .entry(name)
+            .or_default()
+            .push((id, self.in_pattern));
         id
     }
+    fn parse_pattern(&mut self) -> Result<Term, String> {
+        let in_pattern = core::mem::replace(&mut self.in_pattern, true);
+        let pat = self.parse_term_inner();
+        self.in_pattern = in_pattern;
+        pat
+    }
+    /// Parses a term and makes its variables linear.
+    ///
+    /// A variable is bound by the innermost pattern it occurs in, over the
+    /// body of that binder. One used more than once has its pattern
+    /// occurrence replaced by a chain of duplicators with fresh labels, and
+    /// one bound but never used is erased. A variable that only occurs once
+    /// outside of a pattern is free, and shares the scope of the surrounding
+    /// tree.
     pub fn parse_term(&mut self) -> Result<Term, String> {
+        self.skip_trivia();
+        let start = self.index;
+        let outer_vars = core::mem::take(&mut self.term_vars);
+        let outer_subst = core::mem::take(&mut self.term_subst);
+        let outer_pattern = core::mem::replace(&mut self.in_pattern, false);
+        let term = self.parse_term_inner();
+        let free = core::mem::replace(&mut self.term_vars, outer_vars);
+        self.in_pattern = outer_pattern;
+        let bound = self.link_vars(start, free);
+        let subst = core::mem::replace(&mut self.term_subst, outer_subst);
+        let mut term = term?;
+        bound?;
+        term.substitute(&subst);
+        Ok(term)
+    }
+    // Parses a pattern and what it binds, which is everything parsed by
+    // `body` until it returns.
+    fn parse_binding<T>(
+        &mut self,
+        body: impl FnOnce(&mut Self, Term) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let start = self.index;
+        let outer = core::mem::take(&mut self.term_vars);
+        let result = self.parse_pattern().and_then(|pat| body(self, pat));
+        let inner = core::mem::replace(&mut self.term_vars, outer);
+        let (bound, free): (BTreeMap<_, _>, _) = inner
+            .into_iter()
+            .partition(|(_, occurrences)| occurrences.iter().any(|x| x.1));
+        for (name, mut occurrences) in free {
+            self.term_vars
+                .entry(name)
+                .or_default()
+                .append(&mut occurrences);
+        }
+        let result = result?;
+        self.link_vars(start, bound)?;
+        Ok(result)
+    }
+    // Links the occurrences of each variable, duplicating or erasing it where
+    // it is not used exactly once. Errors point at `start`, where the scope of
+    // the variables begins.
+    fn link_vars(
+        &mut self,
+        start: usize,
+        occurrences: BTreeMap<String, Vec<(DefaultKey, bool)>>,
+    ) -> Result<(), String> {
+        for (name, occurrences) in occurrences {
+            let mut sources = occurrences.iter().filter(|x| x.1).map(|x| x.0);
+            match occurrences.as_slice() {
+                [(id, false)] => {
+                    let outer = self.get_or_new(name);
+                    self.term_subst.insert(*id, Term::Var { id: outer });
+                    self.vars.remove(*id);
+                    self.back_scope.remove(id);
+                }
+                [(id, true)] => {
+                    let eraser = Term::Sup {
+                        label: self.fresh_dup_label(),
+                        fst: Box::new(Term::Var { id: *id }),
+                        snd: Box::new(Term::Var { id: *id }),
+                    };
+                    self.term_subst.insert(*id, eraser);
+                }
+                [(a, _), (b, _)] => {
+                    self.term_subst.insert(*b, Term::Var { id: *a });
+                    self.vars.remove(*b);
+                    self.back_scope.remove(b);
+                }
+                _ => {
+                    let (Some(source), None) = (sources.next(), sources.next()) else {
+                        return self.expected_at(
+                            start,
+                            &format!(
+                                "variable `{name}`, which occurs {} times, in exactly one pattern",
+                                occurrences.len()
+                            ),
+                        );
+                    };
+                    let mut uses = occurrences.iter().filter(|x| x.0 != source).rev();
+                    let mut dup = Term::Var {
+                        id: uses.next().unwrap().0,
+                    };
+                    for (id, _) in uses {
+                        dup = Term::Sup {
+                            label: self.fresh_dup_label(),
+                            fst: Box::new(Term::Var { id: *id }),
+                            snd: Box::new(dup),
+                        };
+                    }
+                    self.term_subst.insert(source, dup);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn parse_term_inner(&mut self) -> Result<Term, String> {
         self.skip_trivia();
         let label = match self.peek_one() {
             Some('#') => {
@@ -120,13 +275,12 @@ impl<'i> TreeParser<'i> {
         match self.peek_one() {
             Some(delim @ ('λ' | '@' | 'θ')) => {
                 self.consume(&delim.to_string())?;
-                let pat = self.parse_term()?;
-                let bod = self.parse_term()?;
+                let (pat, bod) =
+                    self.parse_binding(|this, pat| Ok((pat, this.parse_term_inner()?)))?;
 
                 let label = match delim {
-                    'λ' | '@' => NodeLabel(label.unwrap_or(0) * 2),
                     'θ' => NodeLabel::ANN,
-                    _ => todo!(),
+                    _ => NodeLabel(label.unwrap_or(0) * 2),
                 };
 
                 Ok(Term::Binder {
@@ -137,30 +291,28 @@ impl<'i> TreeParser<'i> {
             }
             Some(delim @ ('{' | '<' | '(')) => {
                 self.consume(&delim.to_string())?;
-                let mut fun = self.parse_term()?;
+                let mut fun = self.parse_term_inner()?;
                 self.skip_trivia();
                 while closing(delim) != self.peek_one() && self.peek_one().is_some() {
                     // <a : b : c>
                     // <<a: b> : c>
-                    let label = if delim == '<' {
-                        self.skip_trivia();
-                        if self.peek_many(2) == Some("==") {
-                            self.consume("==")?;
-                            NodeLabel::EQL
-                        } else if self.peek_many(1) == Some(":") {
-                            self.consume(":")?;
-                            NodeLabel::ANN
-                        } else {
-                            todo!()
+                    let label = match delim {
+                        '<' => {
+                            self.skip_trivia();
+                            if self.peek_many(2) == Some("==") {
+                                self.consume("==")?;
+                                NodeLabel::EQL
+                            } else if self.peek_many(1) == Some(":") {
+                                self.consume(":")?;
+                                NodeLabel::ANN
+                            } else {
+                                return self.expected("`:` or `==`");
+                            }
                         }
-                    } else if delim == '(' {
-                        NodeLabel(label.unwrap_or(0) * 2)
-                    } else if delim == '{' {
-                        NodeLabel(label.unwrap_or(0) * 2 + 1)
-                    } else {
-                        todo!()
+                        '(' => NodeLabel(label.unwrap_or(0) * 2),
+                        _ => NodeLabel(label.unwrap_or(0) * 2 + 1),
                     };
-                    let arg = self.parse_term()?;
+                    let arg = self.parse_term_inner()?;
                     fun = match label {
                         NodeLabel::ANN => Term::Apply {
                             label,
@@ -182,8 +334,39 @@ impl<'i> TreeParser<'i> {
                 self.consume(&closing(delim).unwrap().to_string())?;
                 Ok(fun)
             }
+            Some('?') => Ok(Term::Var {
+                id: self.parse_hole()?,
+            }),
             _ => {
                 let name = self.parse_name()?;
+                if name == "let" {
+                    // let pat = value; next
+                    // The pattern only binds in `next`, so the value's
+                    // variables belong to the enclosing scope.
+                    let mut value_vars = BTreeMap::new();
+                    let (pat, value, next) = self.parse_binding(|this, pat| {
+                        this.skip_trivia();
+                        this.consume("=")?;
+                        let bound = core::mem::take(&mut this.term_vars);
+                        let value = this.parse_term_inner();
+                        value_vars = core::mem::replace(&mut this.term_vars, bound);
+                        let value = value?;
+                        this.skip_trivia();
+                        this.consume(";")?;
+                        Ok((pat, value, this.parse_term_inner()?))
+                    })?;
+                    for (name, mut occurrences) in value_vars {
+                        self.term_vars
+                            .entry(name)
+                            .or_default()
+                            .append(&mut occurrences);
+                    }
+                    return Ok(Term::Let {
+                        pat: Box::new(pat),
+                        value: Box::new(value),
+                        next: Box::new(next),
+                    });
+                }
                 let var_id = if name == "tree" {
                     let tree = self.parse_tree()?;
                     self.to_var(tree)
@@ -191,7 +374,7 @@ impl<'i> TreeParser<'i> {
                     let tree = self.inject(net.clone());
                     self.to_var(tree)
                 } else {
-                    self.get_or_new(name)
+                    self.term_var(name)
                 };
                 Ok(Term::Var { id: var_id })
             }
@@ -199,6 +382,22 @@ impl<'i> TreeParser<'i> {
     }
     pub fn parse_tree(&mut self) -> Result<Tree, String> {
         self.skip_trivia();
+        // Nodes can be written with the delimiters declared for their label.
+        let rest = &self.input[self.index..];
+        let declared = self.labels.delimiters().filter(|x| rest.starts_with(x.1));
+        if let Some((label, open, close)) = declared.max_by_key(|x| x.1.len()) {
+            let close = close.to_string();
+            self.index += open.len();
+            let p1 = self.parse_tree()?;
+            let p2 = self.parse_tree()?;
+            self.skip_trivia();
+            self.consume(&close)?;
+            return Ok(Tree::Binary {
+                label,
+                p1: Box::new(p1),
+                p2: Box::new(p2),
+            });
+        }
         match self.peek_one() {
             Some(delim @ ('(' | '[' | '<' | '{')) => {
                 self.consume(&delim.to_string())?;
@@ -225,6 +424,9 @@ impl<'i> TreeParser<'i> {
                     p2: Box::new(p2),
                 })
             }
+            Some('?') => Ok(Tree::Var {
+                id: self.parse_hole()?,
+            }),
             _ => {
                 let name = self.parse_name()?;
                 if name == "term" {
@@ -241,14 +443,30 @@ impl<'i> TreeParser<'i> {
         }
     }
     pub fn parse_net(&mut self) -> Result<Net, String> {
-        let mut net = Net {
+        let net = Net {
             root: self.parse_tree()?,
-            redexes: vec![],
+            redexes: Default::default(),
             vars: Default::default(),
+            names: Default::default(),
+            holes: Default::default(),
+            labels: Default::default(),
         };
         self.skip_trivia();
         while self.peek_one() == Some('&') {
             self.consume("&")?;
+            self.skip_trivia();
+            if self.peek_one() == Some('?') {
+                // & ?name = tree
+                let start = self.index;
+                self.consume("?")?;
+                let name = self.parse_name()?;
+                self.skip_trivia();
+                self.consume("=")?;
+                let tree = self.parse_tree()?;
+                self.insert_hole(start, name, tree)?;
+                self.skip_trivia();
+                continue;
+            }
             let a = self.parse_tree()?;
             self.skip_trivia();
             self.consume("=")?;
@@ -256,24 +474,197 @@ impl<'i> TreeParser<'i> {
             self.redexes.push((a, b));
             self.skip_trivia();
         }
+        self.finish_net(net)
+    }
+    // Moves the variables and redexes parsed so far into the net.
+    fn finish_net(&mut self, mut net: Net) -> Result<Net, String> {
         core::mem::swap(&mut net.vars, &mut self.vars);
-        core::mem::swap(&mut net.redexes, &mut self.redexes);
-        net.validate();
-        self.back_scope.clear();
+        net.redexes = core::mem::take(&mut self.redexes).into();
+        net.names = core::mem::take(&mut self.back_scope);
+        net.holes = core::mem::take(&mut self.holes);
+        net.validate()?;
         Ok(net)
     }
+    // Consumes `word` if it is not the start of a longer name.
+    fn parse_keyword(&mut self, word: &str) -> bool {
+        self.skip_trivia();
+        let rest = &self.input[self.index..];
+        let after = rest.get(word.len()..).and_then(|x| x.chars().next());
+        if rest.starts_with(word)
+            && !after.is_some_and(|c| c.is_ascii_alphanumeric() || "_.-/$".contains(c))
+        {
+            self.index += word.len();
+            true
+        } else {
+            false
+        }
+    }
+    pub fn parse_label(&mut self) -> Result<NodeLabel, String> {
+        let start = self.index;
+        let name = self.parse_name()?;
+        Ok(match name.as_str() {
+            "CON" => NodeLabel::CON,
+            "DUP" => NodeLabel::DUP,
+            "ANN" => NodeLabel::ANN,
+            "EQL" => NodeLabel::EQL,
+            _ => match name.parse() {
+                Ok(label) => NodeLabel(label),
+                Err(_) => return self.expected_at(start, "a label"),
+            },
+        })
+    }
+    // check [not] term : type
+    fn parse_check(&mut self, n: usize) -> Result<Test, String> {
+        self.consume("check ")?;
+        let coherent = !self.parse_keyword("not");
+        let term = self.parse_term()?;
+        self.skip_trivia();
+        self.consume(":")?;
+        let ty = self.parse_term()?;
+        let term = term.encode(&mut self.vars, &mut self.redexes);
+        let ty = ty.encode(&mut self.vars, &mut self.redexes);
+        let root = encode_check(term, ty, &mut self.vars, &mut self.redexes);
+        let net = self.finish_net(Net {
+            root,
+            redexes: Default::default(),
+            vars: Default::default(),
+            names: Default::default(),
+            holes: Default::default(),
+            labels: Default::default(),
+        })?;
+        Ok(Test {
+            name: format!("check#{n}"),
+            net,
+            expect: Expect::Coherence(coherent),
+        })
+    }
+    // rule <label> ~ <label> => tree = tree & tree = tree ...
+    fn parse_rule(&mut self) -> Result<(), String> {
+        let start = self.index;
+        self.consume("rule ")?;
+        self.skip_trivia();
+        let a = self.parse_label()?;
+        self.skip_trivia();
+        self.consume("~")?;
+        self.skip_trivia();
+        let b = self.parse_label()?;
+        self.skip_trivia();
+        self.consume("=>")?;
+        let ports = ["a1", "a2", "b1", "b2"].map(|name| self.get_or_new(name.to_string()));
+        let mut links = vec![];
+        loop {
+            let x = self.parse_tree()?;
+            self.skip_trivia();
+            self.consume("=")?;
+            let y = self.parse_tree()?;
+            links.push((x, y));
+            self.skip_trivia();
+            if self.peek_one() != Some('&') {
+                break;
+            }
+            self.consume("&")?;
+        }
+        links.append(&mut self.redexes);
+        let vars = core::mem::take(&mut self.vars);
+        let names = core::mem::take(&mut self.back_scope);
+        self.scope.clear();
+
+        let mut counts: BTreeMap<DefaultKey, u64> = BTreeMap::new();
+        let mut count = |tree: &Tree| {
+            tree.recurse_ref(&mut |tree| {
+                if let Tree::Var { id } = tree {
+                    *counts.entry(*id).or_default() += 1;
+                }
+            })
+        };
+        for (x, y) in &links {
+            count(x);
+            count(y);
+        }
+        vars.values().flatten().for_each(count);
+        for (id, value) in &vars {
+            let found = counts.get(&id).copied().unwrap_or_default();
+            let expected = if ports.contains(&id) || value.is_some() {
+                1
+            } else {
+                2
+            };
+            if found != expected {
+                let name = names.get(&id).cloned().unwrap_or(format!("{id:?}"));
+                return self.expected_at(
+                    start,
+                    &format!(
+                        "{expected} occurrences of `{name}` in rule {} ~ {}, but `{name}` occurs {found} times",
+                        a.0, b.0
+                    ),
+                );
+            }
+        }
+        self.labels.add_rule(a, b, Rule { ports, vars, links });
+        Ok(())
+    }
+    // label <label> [transparent | opaque] [annihilate | commute] ["open" "close"]
+    fn parse_label_semantics(&mut self) -> Result<(), String> {
+        self.consume("label ")?;
+        self.skip_trivia();
+        let label = self.parse_label()?;
+        let mut semantics = self.labels.get(label).into_owned();
+        loop {
+            if self.parse_keyword("transparent") {
+                semantics.transparent = true;
+            } else if self.parse_keyword("opaque") {
+                semantics.transparent = false;
+            } else if self.parse_keyword("annihilate") {
+                semantics.annihilate = true;
+            } else if self.parse_keyword("commute") {
+                semantics.annihilate = false;
+            } else if self.peek_one() == Some('"') {
+                let start = self.index;
+                semantics.open = self.parse_quoted_string()?;
+                self.skip_trivia();
+                semantics.close = self.parse_quoted_string()?;
+                if semantics.open.trim().is_empty() || semantics.close.trim().is_empty() {
+                    return self.expected_at(start, "delimiters that are not blank");
+                }
+            } else {
+                break;
+            }
+        }
+        self.labels.set(label, semantics);
+        Ok(())
+    }
     pub fn parse_book(&mut self) -> Result<Book, String> {
         self.skip_trivia();
         let mut tests = vec![];
-        while self.peek_many(4) == Some("def ") {
+        let mut checks = 0;
+        loop {
+            if self.peek_many(6) == Some("label ") {
+                self.parse_label_semantics()?;
+                self.skip_trivia();
+                continue;
+            }
+            if self.peek_many(6) == Some("check ") {
+                checks += 1;
+                let test = self.parse_check(checks)?;
+                tests.push(test);
+                self.skip_trivia();
+                continue;
+            }
+            if self.peek_many(5) == Some("rule ") {
+                self.parse_rule()?;
+                self.skip_trivia();
+                continue;
+            }
+            if self.peek_many(4) != Some("def ") {
+                break;
+            }
             self.consume("def ")?;
             self.skip_trivia();
             let name = self.parse_name()?;
-            let name = if name == "test" {
+            let is_test = name == "test";
+            let name = if is_test {
                 self.skip_trivia();
-                let name = self.parse_name()?;
-                tests.push(name.clone());
-                name
+                self.parse_name()?
             } else {
                 name
             };
@@ -281,20 +672,186 @@ impl<'i> TreeParser<'i> {
             self.consume("=")?;
             let value = self.parse_net()?;
             self.skip_trivia();
+            if is_test {
+                // def test name = net ~> expected
+                let expect = if self.peek_many(2) == Some("~>") {
+                    self.consume("~>")?;
+                    let expected = self.parse_net()?;
+                    self.skip_trivia();
+                    Expect::NormalForm(expected)
+                } else {
+                    Expect::Report
+                };
+                tests.push(Test {
+                    name: name.clone(),
+                    net: value.clone(),
+                    expect,
+                });
+            }
             self.defs.insert(name, value);
         }
-        let root = self.parse_net()?;
+        let mut root = self.parse_net()?;
+        let labels = Arc::new(core::mem::take(&mut self.labels));
+        let nets = self
+            .defs
+            .values_mut()
+            .chain(tests.iter_mut().flat_map(|test| match &mut test.expect {
+                Expect::NormalForm(net) => vec![&mut test.net, net],
+                Expect::Report | Expect::Coherence(_) => vec![&mut test.net],
+            }));
+        for net in nets.chain([&mut root]) {
+            net.labels = labels.clone();
+        }
         Ok(Book {
             root,
             defs: core::mem::take(&mut self.defs),
             tests,
+            labels,
+            reducer: Default::default(),
         })
     }
 }
 
-#[derive(Debug)]
-pub struct Book {
-    pub defs: BTreeMap<String, Net>,
-    pub tests: Vec<String>,
-    pub root: Net,
+#[test]
+fn single_hash_is_not_trivia() {
+    let mut parser = TreeParser::new("## comment\n#1 (a b)");
+    assert!(parser.parse_term().is_ok());
+}
+
+#[test]
+fn term_round_trip() {
+    fn show(src: &str) -> String {
+        let mut parser = TreeParser::new(src);
+        let term = parser.parse_term().unwrap();
+        term.display(&parser.vars, &parser.back_scope)
+    }
+    for src in [
+        "λx x",
+        "θf λx <(f <x: A>): B>",
+        "<a == b: c>",
+        "let (f x) = g; {f x}",
+        "λx let #3 {a b} = x; #1 (a b)",
+        "(f tree (y y))",
+    ] {
+        let once = show(src);
+        assert_eq!(once, show(&once), "{src}");
+    }
+    assert_eq!(show("let #3 {a b} = x; (a b)"), "let #3 {a b} = x; (a b)");
+}
+
+#[test]
+fn let_encodes_to_redex() {
+    let book = TreeParser::new("def Swap = term λx let {a b} = x; {b a}\nSwap")
+        .parse_book()
+        .unwrap();
+    assert_eq!(book.defs["Swap"].redexes.len(), 1);
+}
+
+#[test]
+fn non_linear_variables() {
+    let mut book = TreeParser::new("def True = ((a a) (b b))\nterm (λx {x x} True)")
+        .parse_book()
+        .unwrap();
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(
+        book.root.display(),
+        "{1 ((x0 x0) (x1 x1)) ((x2 x2) (x3 x3))}"
+    );
+    let mut book = TreeParser::new("term λx λy x").parse_book().unwrap();
+    book.root.normal(|_| ());
+    book.root.validate().unwrap();
+    assert!(TreeParser::new("term (x x x)").parse_book().is_err());
+}
+
+#[test]
+fn term_variables_are_scoped() {
+    let mut book = TreeParser::new("def I = term (λx x λx x)\nterm (I λy y)")
+        .parse_book()
+        .unwrap();
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(book.root.display(), "(y y)");
+    // The pattern of a `let` does not bind in its value.
+    let mut book = TreeParser::new("term λx let y = x; λx (x y)")
+        .parse_book()
+        .unwrap();
+    book.root.normal(|_| ());
+    book.root.validate().unwrap();
+}
+
+#[test]
+fn check_directives() {
+    let book = TreeParser::new(
+        "def Unit = <(x x) (y y)>
+         def Unit.new = (x x)
+         def Bool.true = ((a a) (b b))
+         check Unit.new : Unit
+         check not Bool.true : Unit
+         check Unit.new : Unit
+         (a a)",
+    )
+    .parse_book()
+    .unwrap();
+    let names: Vec<_> = book.tests.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["check#1", "check#2", "check#3"]);
+    assert_eq!(book.defs.len(), 3);
+    for test in &book.tests {
+        let mut net = test.net.clone();
+        net.normal(|_| ());
+        assert!(matches!(test.expect, Expect::Coherence(x) if Some(x) == net.is_coherent()));
+    }
+}
+
+#[test]
+fn typed_holes() {
+    let mut book = TreeParser::new("def Id = (x x)\nterm (Id ?h)")
+        .parse_book()
+        .unwrap();
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(book.root.display(), "x & ?h = x");
+    let net = TreeParser::new("(a b) & ?h = (b a)").parse_net().unwrap();
+    assert_eq!(net.display(), "(a b) & ?h = (b a)");
+    assert!(TreeParser::new("term (?h ?h)").parse_book().is_err());
+}
+
+#[test]
+fn errors_point_at_source() {
+    for (src, message, line) in [
+        ("term (?h ?h)", "hole `?h`", "1 | term (?h "),
+        ("term (x x x)", "variable `x`", "1 | term "),
+        ("label X commute\n(a a)", "a label", "1 | label "),
+        (
+            "rule 5 ~ 6 => a1 = b1\n(a a)",
+            "`a2` occurs 0 times",
+            "1 | ",
+        ),
+    ] {
+        let err = TreeParser::new(src).parse_book().unwrap_err();
+        assert!(err.contains(message) && err.contains(line), "{err}");
+    }
+}
+
+#[test]
+fn expected_normal_forms() {
+    let book = TreeParser::new(
+        "def Id = (x x)
+         def test id = (Id (a b)) & (a b) = (c c) ~> (y y) & (z z) = (w w)
+         def test plain = (x x)
+         (x x)",
+    )
+    .parse_book()
+    .unwrap();
+    let Expect::NormalForm(expected) = &book.tests[0].expect else {
+        panic!("{:?}", book.tests[0].expect)
+    };
+    assert_eq!(expected.redexes.len(), 1);
+    assert!(matches!(book.tests[1].expect, Expect::Report));
+    for src in [
+        "def test t = (x x) ~>\n(x x)",
+        "def test t = (x x) ~> (y\n(x x)",
+    ] {
+        assert!(TreeParser::new(src).parse_book().is_err(), "{src}");
+    }
 }
@@ -0,0 +1,115 @@
+//! A small layout engine: documents of text and line breaks, where each group
+//! is laid out on one line if it fits in the width, and broken otherwise.
+
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// A space if its group is flat, a newline otherwise.
+    Line,
+    /// Always a newline. The groups around it are never flat.
+    HardLine,
+    /// Indents the lines broken inside by this many more columns.
+    Nest(usize, Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    pub fn text(s: impl Into<String>) -> Doc {
+        Doc::Text(s.into())
+    }
+    pub fn nest(indent: usize, doc: Doc) -> Doc {
+        Doc::Nest(indent, Box::new(doc))
+    }
+    pub fn group(doc: Doc) -> Doc {
+        Doc::Group(Box::new(doc))
+    }
+    /// `open`, then the items separated by lines, then `close`. When broken,
+    /// the items after the first are indented on their own lines.
+    pub fn delimited(open: &str, items: Vec<Doc>, close: &str) -> Doc {
+        let mut inner = vec![];
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                inner.push(Doc::Line);
+            }
+            inner.push(item);
+        }
+        Doc::group(Doc::Concat(vec![
+            Doc::text(open),
+            Doc::nest(2, Doc::Concat(inner)),
+            Doc::text(close),
+        ]))
+    }
+    /// Lays out the document in `width` columns, when possible.
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut column = 0;
+        let mut stack = vec![(0, false, self)];
+        while let Some((indent, flat, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    out += s;
+                    column += s.chars().count();
+                }
+                Doc::Line if flat => {
+                    out.push(' ');
+                    column += 1;
+                }
+                Doc::Line | Doc::HardLine => {
+                    out.truncate(out.trim_end_matches(' ').len());
+                    out.push('\n');
+                    out.extend(std::iter::repeat_n(' ', indent));
+                    column = indent;
+                }
+                Doc::Nest(n, doc) => stack.push((indent + n, flat, doc)),
+                Doc::Group(doc) => {
+                    let flat = flat || fits(width as isize - column as isize, doc, &stack);
+                    stack.push((indent, flat, doc));
+                }
+                Doc::Concat(docs) => {
+                    stack.extend(docs.iter().rev().map(|doc| (indent, flat, doc)));
+                }
+            }
+        }
+        out
+    }
+}
+
+// Whether `doc` laid out flat, followed by the rest of the line, fits in
+// `remaining` columns.
+fn fits(mut remaining: isize, doc: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut rest = rest.iter().rev().map(|&(_, flat, doc)| (flat, doc));
+    let mut stack = vec![(true, doc)];
+    loop {
+        let Some((flat, doc)) = stack.pop().or_else(|| rest.next()) else {
+            return true;
+        };
+        match doc {
+            Doc::Text(s) => remaining -= s.chars().count() as isize,
+            Doc::Line if flat => remaining -= 1,
+            Doc::Line => return true,
+            Doc::HardLine => return !flat,
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push((flat, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (flat, doc))),
+        }
+        if remaining < 0 {
+            return false;
+        }
+    }
+}
+
+#[test]
+fn breaks_groups() {
+    let leaf = |s: &str| Doc::text(s);
+    let doc = Doc::delimited(
+        "(",
+        vec![
+            leaf("fun"),
+            Doc::delimited("(", vec![leaf("a"), leaf("b")], ")"),
+            leaf("argument"),
+        ],
+        ")",
+    );
+    assert_eq!(doc.render(80), "(fun (a b) argument)");
+    assert_eq!(doc.render(12), "(fun\n  (a b)\n  argument)");
+}
@@ -1,102 +1,669 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
 use slotmap::DefaultKey as SlotKey;
 
-use crate::tree::{Net, Tree};
+use crate::{
+    labels::{LabelTable, Rule},
+    tree::{Net, Tree},
+};
 
-impl Net {
-    fn wire(&mut self) -> (SlotKey, SlotKey) {
-        let key = self.vars.insert(None);
-        (key, key)
+/// How nets are reduced to normal form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reducer {
+    /// One redex at a time, in this order, with `Net::normal_with`.
+    Sequential(Strategy),
+    /// On this many threads, with `Net::normal_parallel`.
+    Parallel(usize),
+    /// Only until the root is headed by a node, with `Net::weak_normal`.
+    Lazy,
+}
+
+impl Default for Reducer {
+    fn default() -> Self {
+        Reducer::Sequential(Strategy::Lifo)
+    }
+}
+
+/// The order in which `Net::normal_with` picks redexes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// The most recent redex first.
+    #[default]
+    Lifo,
+    /// The oldest redex first.
+    Fifo,
+    /// Any redex, drawn from a generator seeded with this.
+    Random(u64),
+    /// The most recent redex connected to the root, through wires and other
+    /// such redexes, when it was found. Redexes that never reach the root are
+    /// left unreduced.
+    RootFirst,
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Strategy::Lifo => write!(f, "lifo"),
+            Strategy::Fifo => write!(f, "fifo"),
+            Strategy::Random(seed) => write!(f, "random:{seed}"),
+            Strategy::RootFirst => write!(f, "root-first"),
+        }
     }
-    fn wire_tree(&mut self) -> (Tree, Tree) {
-        let (a, b) = self.wire();
-        (Tree::Var { id: a }, Tree::Var { id: b })
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReductionStats {
+    pub interactions: usize,
+    /// The most nodes the net had at any point.
+    pub max_size: usize,
+}
+
+/// Where interactions keep their wires and new redexes, so that the same
+/// rules can run on a `Net` or on a store shared between threads.
+pub(crate) trait Wires {
+    fn labels(&self) -> Arc<LabelTable>;
+    fn wire(&mut self) -> SlotKey;
+    /// Connects one end of wire `id` to `tree`. If the other end was already
+    /// connected, frees the wire and returns what it was connected to, along
+    /// with `tree`.
+    fn connect(&mut self, id: SlotKey, tree: Tree) -> Option<(Tree, Tree)>;
+    /// Connects one end of a fresh wire to `tree`.
+    fn bind(&mut self, id: SlotKey, tree: Tree);
+    fn link(&mut self, a: Tree, b: Tree);
+}
+
+impl Wires for Net {
+    fn labels(&self) -> Arc<LabelTable> {
+        self.labels.clone()
+    }
+    fn wire(&mut self) -> SlotKey {
+        self.vars.insert(None)
+    }
+    fn connect(&mut self, id: SlotKey, tree: Tree) -> Option<(Tree, Tree)> {
+        let entry = self.vars.get_mut(id).unwrap();
+        if entry.is_some() {
+            Some((self.vars.remove(id).unwrap().unwrap(), tree))
+        } else {
+            *entry = Some(tree);
+            None
+        }
+    }
+    fn bind(&mut self, id: SlotKey, tree: Tree) {
+        self.vars[id] = Some(tree);
     }
     fn link(&mut self, a: Tree, b: Tree) {
-        self.redexes.push((a, b));
-    }
-    fn interact(&mut self, a: Tree, b: Tree) {
-        use Tree::*;
-        match (a, b) {
-            (Var { id }, b) => {
-                let entry = self.vars.get_mut(id).unwrap();
-                if entry.is_some() {
-                    let a = self.vars.remove(id).unwrap().unwrap();
-                    self.interact(a, b)
+        self.redexes.push_back((a, b));
+    }
+}
+
+fn wire_tree(wires: &mut impl Wires) -> (Tree, Tree) {
+    let id = wires.wire();
+    (Tree::Var { id }, Tree::Var { id })
+}
+
+/// Reduces the redex between `a` and `b`. Returns how many more nodes there
+/// are afterwards.
+pub(crate) fn interact(wires: &mut impl Wires, a: Tree, b: Tree) -> isize {
+    use Tree::*;
+    match (a, b) {
+        (Var { id }, b) => match wires.connect(id, b) {
+            Some((a, b)) => interact(wires, a, b),
+            None => 0,
+        },
+        (a, Var { id }) => match wires.connect(id, a) {
+            Some((b, a)) => interact(wires, a, b),
+            None => 0,
+        },
+        (
+            Binary {
+                label: a0,
+                p1: box a1,
+                p2: box a2,
+            },
+            Binary {
+                label: b0,
+                p1: box b1,
+                p2: box b2,
+            },
+        ) => {
+            let labels = wires.labels();
+            if let Some((rule, swapped)) = labels.rule(a0, b0) {
+                let ports = if swapped {
+                    [b1, b2, a1, a2]
                 } else {
-                    *entry = Some(b);
-                }
+                    [a1, a2, b1, b2]
+                };
+                apply_rule(wires, rule, ports) - 2
+            } else if a0 == b0 && labels.annihilates(a0) {
+                wires.link(a1, b1);
+                wires.link(a2, b2);
+                -2
+            } else {
+                let (a11, b11) = wire_tree(wires);
+                let (a12, b12) = wire_tree(wires);
+                let (a21, b21) = wire_tree(wires);
+                let (a22, b22) = wire_tree(wires);
+
+                wires.link(
+                    a1,
+                    Binary {
+                        label: b0,
+                        p1: Box::new(a11),
+                        p2: Box::new(a12),
+                    },
+                );
+                wires.link(
+                    a2,
+                    Binary {
+                        label: b0,
+                        p1: Box::new(a21),
+                        p2: Box::new(a22),
+                    },
+                );
+                wires.link(
+                    b1,
+                    Binary {
+                        label: a0,
+                        p1: Box::new(b11),
+                        p2: Box::new(b21),
+                    },
+                );
+                wires.link(
+                    b2,
+                    Binary {
+                        label: a0,
+                        p1: Box::new(b12),
+                        p2: Box::new(b22),
+                    },
+                );
+                2
             }
-            (a, Var { id }) => {
-                let entry = self.vars.get_mut(id).unwrap();
-                if entry.is_some() {
-                    let b = self.vars.remove(id).unwrap().unwrap();
-                    self.interact(a, b)
-                } else {
-                    *entry = Some(a);
-                }
+        }
+    }
+}
+
+// Links a fresh copy of the rule's net, with its ports replaced by the trees
+// connected to the auxiliary ports of the active pair. Returns the number of
+// nodes created.
+fn apply_rule(wires: &mut impl Wires, rule: &Rule, ports: [Tree; 4]) -> isize {
+    fn instantiate(
+        tree: &Tree,
+        remap: &BTreeMap<SlotKey, SlotKey>,
+        ports: &mut BTreeMap<SlotKey, Tree>,
+    ) -> Tree {
+        match tree {
+            Tree::Binary { label, p1, p2 } => Tree::Binary {
+                label: *label,
+                p1: Box::new(instantiate(p1, remap, ports)),
+                p2: Box::new(instantiate(p2, remap, ports)),
+            },
+            Tree::Var { id } => match ports.remove(id) {
+                Some(port) => port,
+                None => Tree::Var { id: remap[id] },
+            },
+        }
+    }
+    let mut ports: BTreeMap<_, _> = rule.ports.into_iter().zip(ports).collect();
+    let remap: BTreeMap<_, _> = rule
+        .vars
+        .keys()
+        .filter(|id| !ports.contains_key(id))
+        .map(|id| (id, wires.wire()))
+        .collect();
+    let mut created = 0;
+    let links = rule.links.iter().flat_map(|(a, b)| [a, b]);
+    for tree in rule.vars.values().flatten().chain(links) {
+        tree.recurse_ref(&mut |tree| created += !tree.is_var() as isize);
+    }
+    for (id, value) in &rule.vars {
+        if let Some(value) = value {
+            wires.bind(remap[&id], instantiate(value, &remap, &mut ports));
+        }
+    }
+    for (a, b) in &rule.links {
+        let a = instantiate(a, &remap, &mut ports);
+        let b = instantiate(b, &remap, &mut ports);
+        wires.link(a, b);
+    }
+    created
+}
+
+// The next state and output of a splitmix64 generator.
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut x = *state;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Where a wire occurs, other than in the root: in a redex, by key, or in the
+// tree connected to another wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Site {
+    Redex(usize),
+    Wire(SlotKey),
+}
+
+fn index(sites: &mut BTreeMap<SlotKey, BTreeSet<Site>>, tree: &Tree, site: Site, add: bool) {
+    tree.recurse_ref(&mut |tree| {
+        if let Tree::Var { id } = tree {
+            let entry = sites.entry(*id).or_default();
+            if add {
+                entry.insert(site);
+            } else {
+                entry.remove(&site);
             }
-            (
-                Binary {
-                    label: a0,
-                    p1: box a1,
-                    p2: box a2,
-                },
-                Binary {
-                    label: b0,
-                    p1: box b1,
-                    p2: box b2,
-                },
-            ) => {
-                if a0 == b0 {
-                    self.link(a1, b1);
-                    self.link(a2, b2);
-                } else {
-                    let (a11, b11) = self.wire_tree();
-                    let (a12, b12) = self.wire_tree();
-                    let (a21, b21) = self.wire_tree();
-                    let (a22, b22) = self.wire_tree();
-
-                    self.link(
-                        a1,
-                        Binary {
-                            label: b0.clone(),
-                            p1: Box::new(a11),
-                            p2: Box::new(a12),
-                        },
-                    );
-                    self.link(
-                        a2,
-                        Binary {
-                            label: b0.clone(),
-                            p1: Box::new(a21),
-                            p2: Box::new(a22),
-                        },
-                    );
-                    self.link(
-                        b1,
-                        Binary {
-                            label: a0.clone(),
-                            p1: Box::new(b11),
-                            p2: Box::new(b21),
-                        },
-                    );
-                    self.link(
-                        b2,
-                        Binary {
-                            label: a0.clone(),
-                            p1: Box::new(b12),
-                            p2: Box::new(b22),
-                        },
-                    );
+        }
+    });
+}
+
+// The redexes that `Strategy::RootFirst` has found connected to the root or
+// to a hole, and the wires it went through to find them. Redexes stay found
+// once they are, and interactions report what they add, so each step only
+// follows what is new. The net keeps its redexes so that hooks see all of
+// them, but taking one moves the last into its place; `keys` tells the order
+// in which they were created.
+struct Reach<'a> {
+    net: &'a mut Net,
+    sites: BTreeMap<SlotKey, BTreeSet<Site>>,
+    // The key of each redex of the net, by position, and the reverse.
+    keys: Vec<usize>,
+    positions: BTreeMap<usize, usize>,
+    next: usize,
+    wires: BTreeSet<SlotKey>,
+    found: BTreeSet<usize>,
+}
+
+impl<'a> Reach<'a> {
+    fn new(net: &'a mut Net) -> Self {
+        let mut sites = BTreeMap::new();
+        for (key, (a, b)) in net.redexes.iter().enumerate() {
+            index(&mut sites, a, Site::Redex(key), true);
+            index(&mut sites, b, Site::Redex(key), true);
+        }
+        let keys: Vec<_> = (0..net.redexes.len()).collect();
+        let mut reach = Reach {
+            positions: keys.iter().map(|&key| (key, key)).collect(),
+            next: keys.len(),
+            keys,
+            sites,
+            net,
+            wires: BTreeSet::new(),
+            found: BTreeSet::new(),
+        };
+        let mut stack = vec![];
+        for tree in [&reach.net.root]
+            .into_iter()
+            .chain(reach.net.holes.values())
+        {
+            push_vars(&mut stack, tree);
+        }
+        reach.follow(stack);
+        reach
+    }
+    // Follows the wires and the redexes they connect to.
+    fn follow(&mut self, mut stack: Vec<SlotKey>) {
+        while let Some(id) = stack.pop() {
+            if !self.wires.insert(id) {
+                continue;
+            }
+            if let Some(Some(tree)) = self.net.vars.get(id) {
+                push_vars(&mut stack, tree);
+            }
+            for site in self.sites.get(&id).into_iter().flatten() {
+                let Site::Redex(key) = *site else { continue };
+                if self.found.insert(key) {
+                    let (a, b) = &self.net.redexes[self.positions[&key]];
+                    push_vars(&mut stack, a);
+                    push_vars(&mut stack, b);
                 }
             }
         }
     }
-    pub fn normal(&mut self, hook: impl Fn(&mut Self)) {
+    // Takes the most recent found redex out of the net.
+    fn take(&mut self) -> Option<(Tree, Tree)> {
+        let key = self.found.pop_last()?;
+        let i = self.positions.remove(&key).unwrap();
+        let (a, b) = self.net.redexes.swap_remove_back(i).unwrap();
+        self.keys.swap_remove(i);
+        if let Some(&moved) = self.keys.get(i) {
+            self.positions.insert(moved, i);
+        }
+        index(&mut self.sites, &a, Site::Redex(key), false);
+        index(&mut self.sites, &b, Site::Redex(key), false);
+        Some((a, b))
+    }
+    // Puts the redexes left back in the order they were created.
+    fn finish(self) {
+        let mut redexes: Vec<_> = self
+            .keys
+            .into_iter()
+            .zip(self.net.redexes.drain(..))
+            .collect();
+        redexes.sort_by_key(|x| x.0);
+        self.net.redexes = redexes.into_iter().map(|x| x.1).collect();
+    }
+}
+
+fn push_vars(stack: &mut Vec<SlotKey>, tree: &Tree) {
+    tree.recurse_ref(&mut |tree| {
+        if let Tree::Var { id } = tree {
+            stack.push(*id);
+        }
+    });
+}
+
+impl Wires for Reach<'_> {
+    fn labels(&self) -> Arc<LabelTable> {
+        self.net.labels.clone()
+    }
+    fn wire(&mut self) -> SlotKey {
+        self.net.wire()
+    }
+    fn connect(&mut self, id: SlotKey, tree: Tree) -> Option<(Tree, Tree)> {
+        let freed = self.net.connect(id, tree);
+        if freed.is_none() && self.wires.remove(&id) {
+            self.follow(vec![id]);
+        }
+        freed
+    }
+    fn bind(&mut self, id: SlotKey, tree: Tree) {
+        self.net.bind(id, tree);
+        if self.wires.remove(&id) {
+            self.follow(vec![id]);
+        }
+    }
+    fn link(&mut self, a: Tree, b: Tree) {
+        let key = self.next;
+        self.next += 1;
+        index(&mut self.sites, &a, Site::Redex(key), true);
+        index(&mut self.sites, &b, Site::Redex(key), true);
+        let mut stack = vec![];
+        push_vars(&mut stack, &a);
+        push_vars(&mut stack, &b);
+        self.positions.insert(key, self.keys.len());
+        self.keys.push(key);
+        self.net.redexes.push_back((a, b));
+        if stack.iter().any(|id| self.wires.contains(id)) {
+            self.found.insert(key);
+            self.follow(stack);
+        }
+    }
+}
+
+// The redexes of `Net::weak_normal`, by key, and the sites of each wire, kept
+// up to date as interactions move trees around, so that finding the redex the
+// root waits on does not scan the net.
+struct Lazy<'a> {
+    net: &'a mut Net,
+    redexes: BTreeMap<usize, (Tree, Tree)>,
+    next: usize,
+    sites: BTreeMap<SlotKey, BTreeSet<Site>>,
+}
+
+impl<'a> Lazy<'a> {
+    fn new(net: &'a mut Net) -> Self {
+        let mut sites = BTreeMap::new();
+        for (id, tree) in &net.vars {
+            if let Some(tree) = tree {
+                index(&mut sites, tree, Site::Wire(id), true);
+            }
+        }
+        let redexes: BTreeMap<_, _> = core::mem::take(&mut net.redexes)
+            .into_iter()
+            .enumerate()
+            .collect();
+        for (key, (a, b)) in &redexes {
+            index(&mut sites, a, Site::Redex(*key), true);
+            index(&mut sites, b, Site::Redex(*key), true);
+        }
+        Lazy {
+            net,
+            next: redexes.len(),
+            redexes,
+            sites,
+        }
+    }
+    fn take(&mut self, key: usize) -> (Tree, Tree) {
+        let (a, b) = self.redexes.remove(&key).unwrap();
+        index(&mut self.sites, &a, Site::Redex(key), false);
+        index(&mut self.sites, &b, Site::Redex(key), false);
+        (a, b)
+    }
+    // The key of the redex that the root's principal port waits on, if it is
+    // not headed by a node. Substitutes the wires at the top of the root.
+    fn head_redex(&mut self) -> Option<usize> {
+        while let Tree::Var { id } = self.net.root {
+            let Some(tree) = self.net.vars[id].take() else {
+                break;
+            };
+            self.net.vars.remove(id);
+            index(&mut self.sites, &tree, Site::Wire(id), false);
+            self.net.root = tree;
+        }
+        let Tree::Var { id } = self.net.root else {
+            return None;
+        };
+        // Walk from the other end of the root's wire up to a principal port:
+        // either a side of a redex, or the top of a tree connected to a wire,
+        // whose other end we walk from next. Redexes sort first.
+        let mut wire = id;
+        let mut seen = BTreeSet::new();
+        while seen.insert(wire) {
+            let sites = self.sites.get(&wire)?;
+            match sites.iter().find(|x| **x != Site::Wire(wire))? {
+                Site::Redex(key) => return Some(*key),
+                Site::Wire(other) => wire = *other,
+            }
+        }
+        None
+    }
+}
+
+impl Wires for Lazy<'_> {
+    fn labels(&self) -> Arc<LabelTable> {
+        self.net.labels.clone()
+    }
+    fn wire(&mut self) -> SlotKey {
+        self.net.wire()
+    }
+    fn connect(&mut self, id: SlotKey, tree: Tree) -> Option<(Tree, Tree)> {
+        match self.net.connect(id, tree) {
+            Some((old, tree)) => {
+                index(&mut self.sites, &old, Site::Wire(id), false);
+                self.sites.remove(&id);
+                Some((old, tree))
+            }
+            None => {
+                let tree = self.net.vars[id].as_ref().unwrap();
+                index(&mut self.sites, tree, Site::Wire(id), true);
+                None
+            }
+        }
+    }
+    fn bind(&mut self, id: SlotKey, tree: Tree) {
+        index(&mut self.sites, &tree, Site::Wire(id), true);
+        self.net.bind(id, tree);
+    }
+    fn link(&mut self, a: Tree, b: Tree) {
+        index(&mut self.sites, &a, Site::Redex(self.next), true);
+        index(&mut self.sites, &b, Site::Redex(self.next), true);
+        self.redexes.insert(self.next, (a, b));
+        self.next += 1;
+    }
+}
+
+impl Net {
+    /// Reduces the net to normal form, calling `hook` before the first
+    /// interaction and after each one. Returns the number of interactions.
+    pub fn normal(&mut self, hook: impl Fn(&mut Self)) -> usize {
+        self.normal_with(Strategy::Lifo, hook).interactions
+    }
+    /// Reduces the redexes in the order given by `strategy`, calling `hook`
+    /// before the first and after each interaction.
+    pub fn normal_with(&mut self, strategy: Strategy, hook: impl Fn(&mut Self)) -> ReductionStats {
+        if strategy == Strategy::RootFirst {
+            return self.normal_root_first(hook);
+        }
+        let mut size = self.size();
+        let mut stats = ReductionStats {
+            interactions: 0,
+            max_size: size,
+        };
+        let mut random = match strategy {
+            Strategy::Random(seed) => seed,
+            _ => 0,
+        };
         hook(self);
-        while let Some((a, b)) = self.redexes.pop() {
-            self.interact(a, b);
+        loop {
+            let len = self.redexes.len();
+            let next = match strategy {
+                Strategy::Lifo => self.redexes.pop_back(),
+                Strategy::Fifo => self.redexes.pop_front(),
+                Strategy::Random(_) if len > 0 => {
+                    let next = next_random(&mut random) % len as u64;
+                    self.redexes.remove(next as usize)
+                }
+                Strategy::Random(_) | Strategy::RootFirst => None,
+            };
+            let Some((a, b)) = next else {
+                return stats;
+            };
+            size = size.saturating_add_signed(interact(self, a, b));
+            stats.interactions += 1;
+            stats.max_size = stats.max_size.max(size);
             hook(self);
         }
     }
+    fn normal_root_first(&mut self, hook: impl Fn(&mut Self)) -> ReductionStats {
+        let mut size = self.size();
+        let mut stats = ReductionStats {
+            interactions: 0,
+            max_size: size,
+        };
+        hook(self);
+        let mut reach = Reach::new(self);
+        while let Some((a, b)) = reach.take() {
+            size = size.saturating_add_signed(interact(&mut reach, a, b));
+            stats.interactions += 1;
+            stats.max_size = stats.max_size.max(size);
+            hook(reach.net);
+        }
+        reach.finish();
+        stats
+    }
+    /// Reduces the net to normal form with the given reducer.
+    pub fn reduce(&mut self, reducer: Reducer) -> ReductionStats {
+        match reducer {
+            Reducer::Sequential(strategy) => self.normal_with(strategy, |_| ()),
+            Reducer::Parallel(threads) => self.normal_parallel(threads),
+            Reducer::Lazy => self.weak_normal(),
+        }
+    }
+    /// The number of nodes of the net.
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        self.recurse_ref(&mut |tree| size += !tree.is_var() as usize);
+        size
+    }
+    /// Reduces only the redexes that the root's principal port waits on,
+    /// until the root is headed by a node or waits on none. Other redexes,
+    /// including the ones under the head, are left unreduced.
+    pub fn weak_normal(&mut self) -> ReductionStats {
+        let mut size = self.size();
+        let mut stats = ReductionStats {
+            interactions: 0,
+            max_size: size,
+        };
+        let mut lazy = Lazy::new(self);
+        while let Some(key) = lazy.head_redex() {
+            let (a, b) = lazy.take(key);
+            size = size.saturating_add_signed(interact(&mut lazy, a, b));
+            stats.interactions += 1;
+            stats.max_size = stats.max_size.max(size);
+        }
+        self.redexes = lazy.redexes.into_values().collect();
+        stats
+    }
+    /// Like `normal`, but gives up after `limit` interactions. Returns whether
+    /// the net reached its normal form.
+    pub fn normal_within(&mut self, limit: usize) -> bool {
+        for _ in 0..limit {
+            let Some((a, b)) = self.redexes.pop_back() else {
+                return true;
+            };
+            interact(self, a, b);
+        }
+        self.redexes.is_empty()
+    }
+}
+
+#[test]
+fn custom_rules() {
+    use crate::parser::TreeParser;
+    let src = "rule ANN ~ EQL => a1 = b1 & a2 = (x x) & b2 = y & y = (z z)
+               (p (q r)) & [p q] = <r s> & s = (u u)";
+    let mut book = TreeParser::new(src).parse_book().unwrap();
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(book.root.display(), "(p ((x0 x0) p))");
+    let err = TreeParser::new("rule ANN ~ EQL => a1 = b1 & a2 = b1\n(x x)")
+        .parse_book()
+        .unwrap_err();
+    assert!(err.contains("`b2` occurs 0 times"), "{err}");
+    assert!(
+        TreeParser::new("rule CON ~ DUP => a1 = b1 & a2 = b2 & (x y) = (z z)\n(x x)")
+            .parse_book()
+            .is_err()
+    );
+}
+
+#[test]
+fn strategies() {
+    let book = crate::book::Book::parse(include_str!("../sample.itt")).unwrap();
+    for (name, net) in &book.defs {
+        let mut lifo = net.clone();
+        let expected = lifo.normal_with(Strategy::Lifo, |_| ());
+        for strategy in [Strategy::Fifo, Strategy::Random(1), Strategy::Random(2)] {
+            let mut other = net.clone();
+            let stats = other.normal_with(strategy, |_| ());
+            assert_eq!(stats.interactions, expected.interactions, "{name}");
+            assert!(lifo.alpha_eq(&other), "{name} {strategy:?}");
+        }
+        // Reducing what the root does not wait on afterwards ends the same.
+        let mut other = net.clone();
+        other.normal_with(Strategy::RootFirst, |_| ());
+        other.normal(|_| ());
+        assert!(lifo.alpha_eq(&other), "{name} root-first");
+    }
+    let mut net = crate::parser::TreeParser::new("(a b) & [a c] = [b c] & (x x) = (y y)")
+        .parse_net()
+        .unwrap();
+    let stats = net.normal_with(Strategy::RootFirst, |_| ());
+    // The wire `c` left between the two annihilated nodes was found through
+    // them, so it is reduced too, but the unrelated redex is not.
+    assert_eq!((stats.interactions, stats.max_size), (3, 5));
+    net.resolve_vars();
+    assert_eq!(net.display(), "(b b) & (x x) = (y y)");
+}
+
+#[test]
+fn weak_head() {
+    let book = crate::book::Book::parse(
+        "def Bool.true = ((a a) (b b))
+         def Omega = term (λx(x x) λy(y y))
+         term (λaλb a Bool.true Omega)",
+    )
+    .unwrap();
+    let mut lazy = book.root.clone();
+    let stats = lazy.weak_normal();
+    assert!(!lazy.redexes.is_empty());
+    assert!(stats.interactions < book.root.clone().normal(|_| ()));
+    lazy.redexes.clear();
+    lazy.resolve_vars();
+    assert!(lazy.alpha_eq(&book.defs["Bool.true"]));
 }
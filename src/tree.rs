@@ -1,7 +1,13 @@
-use std::{collections::BTreeMap, fmt::Write};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write,
+    sync::Arc,
+};
 
 use slotmap::{DefaultKey as SlotKey, SlotMap};
 
+use crate::{labels::LabelTable, pretty::Doc};
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
 pub struct NodeLabel(pub u64);
 
@@ -27,8 +33,16 @@ pub enum Tree {
 #[derive(Clone, Debug)]
 pub struct Net {
     pub root: Tree,
-    pub redexes: Vec<(Tree, Tree)>,
+    pub redexes: VecDeque<(Tree, Tree)>,
     pub vars: SlotMap<SlotKey, Option<Tree>>,
+    /// Source names of variables, where known. Wires created during
+    /// interaction have none.
+    pub names: BTreeMap<SlotKey, String>,
+    /// The trees connected to the holes `?name` of the term the net was
+    /// written as, which are what each hole must satisfy.
+    pub holes: BTreeMap<String, Tree>,
+    /// Semantics of the labels of the book the net comes from.
+    pub labels: Arc<LabelTable>,
 }
 
 impl Net {
@@ -38,10 +52,11 @@ impl Net {
             a.recurse_mut(f);
             b.recurse_mut(f);
         }
-        for v in self.vars.values_mut() {
-            if let Some(v) = v {
-                v.recurse_mut(f)
-            }
+        for v in self.holes.values_mut() {
+            v.recurse_mut(f);
+        }
+        for v in self.vars.values_mut().flatten() {
+            v.recurse_mut(f)
         }
     }
     pub fn recurse_ref(&self, f: &mut impl FnMut(&Tree)) {
@@ -50,30 +65,42 @@ impl Net {
             a.recurse_ref(f);
             b.recurse_ref(f);
         }
-        for v in self.vars.values() {
-            if let Some(v) = v {
-                v.recurse_ref(f)
-            }
+        for v in self.holes.values() {
+            v.recurse_ref(f);
+        }
+        for v in self.vars.values().flatten() {
+            v.recurse_ref(f)
         }
     }
     pub fn show<'a>(&'a self) -> NetShow<'a, impl Fn(SlotKey) -> Option<&'a Tree>> {
-        NetShow {
-            vars: |key| self.vars.get(key).map(|x| x.as_ref()).flatten(),
-            scope: Default::default(),
-        }
+        NetShow::new(
+            |key| self.vars.get(key).and_then(|x| x.as_ref()),
+            &self.names,
+            &self.labels,
+        )
     }
     pub fn display(&self) -> String {
         let mut s = String::new();
         self.show().show_net(&mut s, self).unwrap();
         s
     }
-    pub fn validate(&self) {
+    /// Like `display`, but breaks trees over lines to fit in `width` columns,
+    /// with one redex per line. With `wires`, trees connected to a wire are
+    /// written once, as `& wire = tree`, instead of in place of the wire.
+    pub fn pretty(&self, width: usize, wires: bool) -> String {
+        let mut show = self.show();
+        show.wires = wires;
+        show.pretty_net(self, width)
+    }
+    /// Checks that every wire has exactly two ends.
+    pub fn validate(&self) -> Result<(), String> {
         for (k, v) in &self.vars {
             if let Some(v) = v {
-                v.recurse_ref(&mut |s| match s {
-                    Tree::Binary { .. } => (),
-                    Tree::Var { id } => assert!(k != *id),
-                })
+                let mut cyclic = false;
+                v.recurse_ref(&mut |s| cyclic |= matches!(s, Tree::Var { id } if k == *id));
+                if cyclic {
+                    return Err(format!("var: {} is bound to itself", self.var_name(k)));
+                }
             }
         }
         let mut counts: BTreeMap<SlotKey, u64> = BTreeMap::new();
@@ -87,7 +114,19 @@ impl Net {
                 Some(None) => 2,
                 None => 0,
             };
-            assert!(v == expect, "var: {k:?} found: {v} != expected: {expect}");
+            if v != expect {
+                return Err(format!(
+                    "var: {} found: {v} != expected: {expect}",
+                    self.var_name(k)
+                ));
+            }
+        }
+        Ok(())
+    }
+    pub fn var_name(&self, key: SlotKey) -> String {
+        match self.names.get(&key) {
+            Some(name) => name.clone(),
+            None => format!("{key:?}"),
         }
     }
     pub fn resolve_vars(&mut self) {
@@ -96,12 +135,60 @@ impl Net {
             a.resolve_vars(&mut self.vars);
             b.resolve_vars(&mut self.vars);
         }
+        for v in self.holes.values_mut() {
+            v.resolve_vars(&mut self.vars);
+        }
     }
-    pub fn is_coherent(&mut self) -> bool {
-        self.resolve_vars();
-        self.root.is_coherent()
+    /// Moves the wires, redexes and holes of `other` into this net, returning
+    /// its root, which is left for the caller to connect. Holes whose name is
+    /// taken get a numeric suffix.
+    pub fn inject(&mut self, other: Net) -> Tree {
+        inject(
+            other,
+            &mut self.vars,
+            &mut self.names,
+            &mut self.redexes,
+            &mut self.holes,
+        )
     }
 }
+
+/// `Net::inject` into a net that is still being built, kept as its parts.
+pub(crate) fn inject(
+    mut other: Net,
+    vars: &mut SlotMap<SlotKey, Option<Tree>>,
+    names: &mut BTreeMap<SlotKey, String>,
+    redexes: &mut impl Extend<(Tree, Tree)>,
+    holes: &mut BTreeMap<String, Tree>,
+) -> Tree {
+    let mut remap = BTreeMap::new();
+    for (k, v) in core::mem::take(&mut other.vars) {
+        let id = vars.insert(v);
+        remap.insert(k, id);
+        if let Some(name) = other.names.remove(&k) {
+            names.insert(id, name);
+        }
+    }
+    let remap_fun = |key: SlotKey| remap.get(&key).cloned();
+    for id in remap.values() {
+        if let Some(Some(v)) = vars.get_mut(*id) {
+            v.recurse_mut(&mut |tree| tree.map_var_id(remap_fun));
+        }
+    }
+    other.recurse_mut(&mut |tree| tree.map_var_id(remap_fun));
+    redexes.extend(other.redexes);
+    for (name, tree) in other.holes {
+        let mut unique = name.clone();
+        let mut suffix = 0;
+        while holes.contains_key(&unique) {
+            suffix += 1;
+            unique = format!("{name}_{suffix}");
+        }
+        holes.insert(unique, tree);
+    }
+    other.root
+}
+
 impl Tree {
     pub fn recurse_ref(&self, f: &mut impl FnMut(&Tree)) {
         f(self);
@@ -140,7 +227,7 @@ impl Tree {
                 let Tree::Var { id } = &term else {
                     unreachable!()
                 };
-                let id = id.clone();
+                let id = *id;
                 if vars.get(id).is_some_and(|x| x.is_some()) {
                     *term = vars.remove(id).unwrap().unwrap()
                 } else {
@@ -152,41 +239,66 @@ impl Tree {
 }
 
 pub struct NetShow<'a, F: Fn(SlotKey) -> Option<&'a Tree>> {
-    vars: F,
+    pub(crate) vars: F,
+    names: &'a BTreeMap<SlotKey, String>,
+    labels: &'a LabelTable,
     scope: BTreeMap<SlotKey, String>,
+    used: BTreeSet<String>,
+    fresh: usize,
+    // Whether to name the wires connected to trees, and the ones named so far.
+    wires: bool,
+    bound: Vec<SlotKey>,
 }
 impl<'a, F: Fn(SlotKey) -> Option<&'a Tree>> NetShow<'a, F> {
-    fn get_or_new(&mut self, name: SlotKey) -> String {
+    pub fn new(vars: F, names: &'a BTreeMap<SlotKey, String>, labels: &'a LabelTable) -> Self {
+        NetShow {
+            vars,
+            names,
+            labels,
+            scope: Default::default(),
+            used: Default::default(),
+            fresh: 0,
+            wires: false,
+            bound: vec![],
+        }
+    }
+    // Variables keep their source name, suffixed if another variable took it
+    // first. Unnamed ones are numbered.
+    pub(crate) fn get_or_new(&mut self, name: SlotKey) -> String {
         if let Some(e) = self.scope.get(&name) {
-            e.clone()
-        } else {
-            let v = format!("x{:?}", self.scope.len());
-            self.scope.insert(name, v.clone());
-            v
+            return e.clone();
         }
+        let v = match self.names.get(&name) {
+            Some(source) => {
+                let mut v = source.clone();
+                let mut suffix = 0;
+                while self.used.contains(&v) {
+                    suffix += 1;
+                    v = format!("{source}_{suffix}");
+                }
+                v
+            }
+            None => loop {
+                let v = format!("x{}", self.fresh);
+                self.fresh += 1;
+                if !self.used.contains(&v) {
+                    break v;
+                }
+            },
+        };
+        self.used.insert(v.clone());
+        self.scope.insert(name, v.clone());
+        v
     }
-    fn show_tree(&mut self, f: &mut impl Write, tree: &'a Tree) -> std::fmt::Result {
+    pub fn show_tree(&mut self, f: &mut impl Write, tree: &'a Tree) -> std::fmt::Result {
         match tree {
             Tree::Binary { label, p1, p2 } => {
-                {
-                    let label = *label;
-                    let label_num = format!("{{{} ", label.0);
-                    f.write_str(match label {
-                        NodeLabel::CON => "(",
-                        NodeLabel::EQL => "[",
-                        NodeLabel::ANN => "<",
-                        _ => &label_num,
-                    })?;
-                };
+                let semantics = self.labels.get(*label);
+                f.write_str(&semantics.open)?;
                 self.show_tree(f, p1)?;
                 f.write_str(" ")?;
                 self.show_tree(f, p2)?;
-                f.write_str(match *label {
-                    NodeLabel::CON => ")",
-                    NodeLabel::EQL => "]",
-                    NodeLabel::ANN => ">",
-                    _ => "}",
-                })?;
+                f.write_str(&semantics.close)?;
             }
             Tree::Var { id } => {
                 if let Some(value) = (self.vars)(*id) {
@@ -198,7 +310,7 @@ impl<'a, F: Fn(SlotKey) -> Option<&'a Tree>> NetShow<'a, F> {
         }
         Ok(())
     }
-    fn show_net(&mut self, f: &mut impl Write, net: &'a Net) -> std::fmt::Result {
+    pub fn show_net(&mut self, f: &mut impl Write, net: &'a Net) -> std::fmt::Result {
         self.show_tree(f, &net.root)?;
         for (a, b) in &net.redexes {
             f.write_str(" & ")?;
@@ -206,6 +318,98 @@ impl<'a, F: Fn(SlotKey) -> Option<&'a Tree>> NetShow<'a, F> {
             f.write_str(" = ")?;
             self.show_tree(f, b)?;
         }
+        for (name, tree) in &net.holes {
+            write!(f, " & ?{name} = ")?;
+            self.show_tree(f, tree)?;
+        }
         Ok(())
     }
+    fn tree_doc(&mut self, tree: &'a Tree) -> Doc {
+        match tree {
+            Tree::Binary { label, p1, p2 } => {
+                let labels = self.labels;
+                let semantics = labels.get(*label);
+                let ports = vec![self.tree_doc(p1), self.tree_doc(p2)];
+                Doc::delimited(&semantics.open, ports, &semantics.close)
+            }
+            Tree::Var { id } => match (self.vars)(*id) {
+                Some(value) if !self.wires => self.tree_doc(value),
+                Some(_) => {
+                    self.bound.push(*id);
+                    Doc::text(self.get_or_new(*id))
+                }
+                None => Doc::text(self.get_or_new(*id)),
+            },
+        }
+    }
+    pub fn pretty_net(&mut self, net: &'a Net, width: usize) -> String {
+        let root = self.tree_doc(&net.root);
+        let mut lines = vec![];
+        let mut line = |a: Doc, b: Doc| {
+            lines.extend([Doc::Line, Doc::text("& "), a, Doc::text(" = "), b]);
+        };
+        for (a, b) in &net.redexes {
+            let a = self.tree_doc(a);
+            line(a, self.tree_doc(b));
+        }
+        for (name, tree) in &net.holes {
+            line(Doc::text(format!("?{name}")), self.tree_doc(tree));
+        }
+        // Trees connected to named wires may name more wires.
+        let mut i = 0;
+        while let Some(&id) = self.bound.get(i) {
+            let name = Doc::text(self.get_or_new(id));
+            line(name, self.tree_doc((self.vars)(id).unwrap()));
+            i += 1;
+        }
+        self.bound.clear();
+        Doc::group(Doc::Concat(vec![root, Doc::nest(2, Doc::Concat(lines))])).render(width)
+    }
+}
+
+#[test]
+fn display_source_names() {
+    use crate::parser::TreeParser;
+    let mut book = TreeParser::new("def Id = (x x)\n(Id (z term (Id z)))")
+        .parse_book()
+        .unwrap();
+    assert_eq!(book.root.display(), "((x x) (z x0)) & (z x0) = (x_1 x_1)");
+    book.root.normal(|_| ());
+    book.root.resolve_vars();
+    assert_eq!(book.root.display(), "((x x) (x_1 x_1))");
+}
+
+#[test]
+fn pretty_nets() {
+    use crate::parser::TreeParser;
+    let book = TreeParser::new("def Id = (x x)\n(Id (z term (Id z)))")
+        .parse_book()
+        .unwrap();
+    assert_eq!(book.root.pretty(80, false), book.root.display());
+    assert_eq!(
+        book.root.pretty(20, false),
+        "((x x) (z x0))\n  & (z x0) = (x_1\n    x_1)"
+    );
+    let wires = book.root.pretty(80, true);
+    assert_eq!(wires, "((x x) (z x0)) & (z x0) = x1 & x1 = (x_1 x_1)");
+    let mut parsed = TreeParser::new(&wires).parse_net().unwrap();
+    let mut root = book.root.clone();
+    parsed.normal(|_| ());
+    root.normal(|_| ());
+    assert!(parsed.alpha_eq(&root));
+}
+
+#[test]
+fn inject_renames_holes() {
+    use crate::parser::TreeParser;
+    let mut net = TreeParser::new("(a b) & ?h = (b a)").parse_net().unwrap();
+    let other = TreeParser::new("(c d) & ?h = (d c)").parse_net().unwrap();
+    let root = net.inject(other);
+    net.root = Tree::Binary {
+        label: NodeLabel::CON,
+        p1: Box::new(net.root.clone()),
+        p2: Box::new(root),
+    };
+    assert_eq!(net.holes.keys().collect::<Vec<_>>(), ["h", "h_1"]);
+    net.validate().unwrap();
 }